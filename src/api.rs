@@ -1,43 +1,79 @@
+use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use warp::hyper::body::Bytes;
 
+use futures::stream::{self, Stream};
 use serde::{Deserialize, Serialize};
-use warp::http::Response;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+use warp::http::{Request, Response};
 use warp::hyper::body::to_bytes;
-use warp::hyper::{Client, Uri};
+use warp::hyper::{client, Body, Client, Uri};
 use warp::reply::Json;
 use warp::Filter;
 
-use crate::node::Node;
+use crate::events::{now_ms, Event};
+use crate::network;
+use crate::node::{LookupMode, Neighbor, Node};
+use crate::ring::Inconsistency;
+use crate::routing::id::{HashIdentifier, IdSpace};
+use crate::tls::TlsConfig;
 
-pub type ChordNode = Node<String, String>;
+pub type ChordNode = Node<String, Blob>;
 
-pub async fn get(node: Arc<ChordNode>, key: String) -> Result<Response<String>, warp::Rejection> {
+// a stored value, the raw request body together with its content type
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Blob {
+    pub content_type: Option<String>,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+// query of the storage routes, e.g. ?lookup=iterative
+#[derive(Deserialize, Clone, Copy)]
+pub struct StorageQuery {
+    // how the owner of the key is found, the default of the node if not set
+    lookup: Option<LookupMode>,
+}
+
+pub async fn get(
+    node: Arc<ChordNode>,
+    key: String,
+    query: StorageQuery,
+) -> Result<Response<Vec<u8>>, warp::Rejection> {
     let b = Response::builder();
     if node.is_crashed().await {
         return Ok(b
             .status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
-            .body("oh no I crashed :(".to_string())
+            .body(b"oh no I crashed :(".to_vec())
             .unwrap());
     }
 
-    match node.lookup(key).await {
+    let mode = query.lookup.unwrap_or(node.config.lookup_mode);
+    match node.lookup_with(key.clone(), mode).await {
         Ok(value) => {
             let b = Response::builder();
             let resp = if let Some(v) = value {
+                let content_type = v
+                    .content_type
+                    .unwrap_or_else(|| "application/octet-stream".to_string());
                 b.status(warp::http::StatusCode::OK)
-                    .header("content-type", "text/plain")
-                    .body(v)
+                    .header("content-type", content_type)
+                    .body(v.data)
             } else {
-                b.status(warp::http::StatusCode::NOT_FOUND)
-                    .body("".to_string())
+                b.status(warp::http::StatusCode::NOT_FOUND).body(Vec::new())
             };
             Ok(resp.unwrap())
         }
         Err(err) => {
-            eprintln!("error in lookup: {:?}", err);
-            Err(warp::reject::reject())
+            warn!(node = %node.address, %key, error = ?err, "error in lookup");
+            // a missing key is a 404, a lookup that failed is not
+            Ok(b.status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+                .body(b"error occured while performing get operation".to_vec())
+                .unwrap())
         }
     }
 }
@@ -45,6 +81,8 @@ pub async fn get(node: Arc<ChordNode>, key: String) -> Result<Response<String>,
 pub async fn put(
     node: Arc<ChordNode>,
     key: String,
+    query: StorageQuery,
+    content_type: Option<String>,
     value: Bytes,
 ) -> Result<Response<String>, warp::Rejection> {
     let b = Response::builder();
@@ -54,14 +92,21 @@ pub async fn put(
             .body("oh no I crashed :(".to_string())
             .unwrap());
     }
-    let body = std::str::from_utf8(&value).unwrap();
+    let blob = Blob {
+        content_type,
+        data: value.to_vec(),
+    };
 
-    let (status, msg) = match node.put(key.clone(), body.to_string()).await {
+    let mode = query.lookup.unwrap_or(node.config.lookup_mode);
+    let (status, msg) = match node.put_with(key.clone(), blob, mode).await {
         Ok(_) => (warp::http::StatusCode::OK, "ok"),
         Err(err) => {
-            eprintln!(
-                "[{:}] error performing put (key={:}, value={:?}): {:?}",
-                node.address, key, value, err
+            warn!(
+                node = %node.address,
+                %key,
+                bytes = value.len(),
+                error = ?err,
+                "error performing put"
             );
             (
                 warp::http::StatusCode::INTERNAL_SERVER_ERROR,
@@ -75,34 +120,540 @@ pub async fn put(
         .unwrap())
 }
 
+pub async fn delete(
+    node: Arc<ChordNode>,
+    key: String,
+    query: StorageQuery,
+) -> Result<Response<String>, warp::Rejection> {
+    let b = Response::builder();
+    if node.is_crashed().await {
+        return Ok(b
+            .status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+            .body("oh no I crashed :(".to_string())
+            .unwrap());
+    }
+
+    let mode = query.lookup.unwrap_or(node.config.lookup_mode);
+    let (status, msg) = match node.delete_with(key.clone(), mode).await {
+        Ok(true) => (warp::http::StatusCode::OK, "ok"),
+        Ok(false) => (warp::http::StatusCode::NOT_FOUND, ""),
+        Err(err) => {
+            warn!(node = %node.address, %key, error = ?err, "error performing delete");
+            (
+                warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "error occured while performing delete operation",
+            )
+        }
+    };
+    Ok(b.status(status).body(msg.to_string()).unwrap())
+}
+
 #[derive(Serialize, Deserialize)]
 struct InfoReponse {
     node_hash: String,
     successor: SocketAddr,
     chord_address: SocketAddr,
     others: Vec<SocketAddr>,
+    fingers: Vec<FingerInfo>,
+    // last predecessor that was detected as failed
+    failed_predecessor: Option<SocketAddr>,
+    predecessor_failures: u64,
+    // chord protocol versions the node can talk
+    protocol_versions: Vec<u32>,
+    // width and hash function of the identifiers of the ring
+    id_space: IdSpace,
+    // all positions of the node on the ring, the fields above
+    // describe the first one
+    vnodes: Vec<VnodeInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct VnodeInfo {
+    node_hash: String,
+    // the virtual node owns the keys in (range_start, node_hash],
+    // not known while it has no predecessor
+    range_start: Option<String>,
+    // part of the ring that the range covers
+    range_share: Option<f64>,
+    successor: SocketAddr,
+    successor_hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FingerInfo {
+    // start of the finger interval (id + 2^i)
+    start: String,
+    // web address of the node the finger points to
+    node: Option<SocketAddr>,
+    node_hash: Option<String>,
 }
 
 pub async fn info(node: Arc<ChordNode>) -> Result<Json, warp::Rejection> {
     if node.is_crashed().await {
         panic!("tried to call info for crashed node");
     }
-    let succ = { node.successor.lock().await.clone() };
+    let first = &node.vnodes[0];
+    let succ = { *first.successor.lock().await };
     let mut resp = InfoReponse {
-        node_hash: format!("{:x}", u64::from(node.id)),
+        node_hash: format!("{:x}", node.id),
         successor: succ.web_addr,
         others: Vec::with_capacity(1),
         chord_address: node.address,
+        fingers: Vec::with_capacity(node.id.bits() as usize),
+        failed_predecessor: first.failed_predecessor.lock().await.map(|p| p.web_addr),
+        predecessor_failures: *first.predecessor_failures.lock().await,
+        protocol_versions: network::SUPPORTED_VERSIONS.to_vec(),
+        id_space: node.config.network.id_space,
+        vnodes: Vec::with_capacity(node.vnodes.len()),
     };
 
-    if let Some(p) = node.predecessor.lock().await.clone() {
+    if let Some(p) = *first.predecessor.lock().await {
         resp.others.push(p.web_addr);
     }
-    if let Some(s) = node.second_successor.lock().await.clone() {
+    for s in first.successor_list.lock().await.iter() {
         resp.others.push(s.web_addr);
     }
 
-    return Ok(warp::reply::json(&resp));
+    for (i, finger) in first.fingers.lock().await.iter().enumerate() {
+        resp.fingers.push(FingerInfo {
+            start: format!("{:x}", first.id.offset(i)),
+            node: finger.map(|f| f.web_addr),
+            node_hash: finger.map(|f| format!("{:x}", f.id)),
+        });
+    }
+
+    for vnode in node.vnodes.iter() {
+        let predecessor = *vnode.predecessor.lock().await;
+        let successor = *vnode.successor.lock().await;
+        resp.vnodes.push(VnodeInfo {
+            node_hash: format!("{:x}", vnode.id),
+            range_start: predecessor.map(|p| format!("{:x}", p.id)),
+            range_share: predecessor.map(|p| (vnode.id - p.id).fraction()),
+            successor: successor.web_addr,
+            successor_hash: format!("{:x}", successor.id),
+        });
+    }
+
+    Ok(warp::reply::json(&resp))
+}
+
+#[derive(Serialize, Deserialize)]
+struct TraceResponse {
+    key: String,
+    key_hash: String,
+    lookup: LookupMode,
+    // the virtual node of this node the lookup started at,
+    // followed by every node it visited
+    hops: Vec<TraceHopInfo>,
+    // virtual node responsible for the key, not set if the lookup failed
+    owner: Option<NeighborInfo>,
+    error: Option<String>,
+    total_ms: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NeighborInfo {
+    node_hash: String,
+    chord_address: SocketAddr,
+    web_address: SocketAddr,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TraceHopInfo {
+    #[serde(flatten)]
+    node: NeighborInfo,
+    // round trip to the node, for recursive lookups
+    // including all following hops
+    latency_ms: f64,
+    error: Option<String>,
+}
+
+impl From<Neighbor> for NeighborInfo {
+    fn from(n: Neighbor) -> Self {
+        NeighborInfo {
+            node_hash: format!("{:x}", n.id),
+            chord_address: n.addr,
+            web_address: n.web_addr,
+        }
+    }
+}
+
+// looks up the owner of the key and returns the path the lookup took
+pub async fn trace(
+    node: Arc<ChordNode>,
+    key: String,
+    query: StorageQuery,
+) -> Result<Response<String>, warp::Rejection> {
+    let b = Response::builder();
+    if node.is_crashed().await {
+        return Ok(b
+            .status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+            .body("oh no I crashed :(".to_string())
+            .unwrap());
+    }
+    let mode = query.lookup.unwrap_or(node.config.lookup_mode);
+    let id = key.hash_id(&node.config.network.id_space);
+    let start = Instant::now();
+    let trace = node.trace(id, mode).await;
+    let resp = TraceResponse {
+        key,
+        key_hash: format!("{:x}", id),
+        lookup: mode,
+        hops: trace
+            .hops
+            .into_iter()
+            .map(|hop| TraceHopInfo {
+                node: hop.node.into(),
+                latency_ms: hop.latency.as_secs_f64() * 1000.0,
+                error: hop.error,
+            })
+            .collect(),
+        owner: trace.owner.as_ref().ok().map(|&owner| owner.into()),
+        error: trace.owner.err(),
+        total_ms: start.elapsed().as_secs_f64() * 1000.0,
+    };
+    let status = if resp.error.is_some() {
+        warp::http::StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        warp::http::StatusCode::OK
+    };
+    Ok(b.status(status)
+        .header("content-type", "application/json")
+        .body(serde_json::to_string(&resp).unwrap())
+        .unwrap())
+}
+
+#[derive(Serialize, Deserialize)]
+struct RingResponse {
+    // whether the successors form a single cycle over all nodes
+    // and every node is the predecessor of its successor
+    consistent: bool,
+    // starting with the successor chain of this node
+    nodes: Vec<RingNodeInfo>,
+    unreachable: Vec<UnreachableInfo>,
+    // node hashes of each cycle of successor pointers
+    cycles: Vec<Vec<String>>,
+    inconsistencies: Vec<InconsistencyInfo>,
+    total_ms: f64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RingNodeInfo {
+    #[serde(flatten)]
+    node: NeighborInfo,
+    predecessor: Option<NeighborInfo>,
+    successors: Vec<NeighborInfo>,
+    // part of the ring the node owns, not known without a predecessor
+    range_share: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UnreachableInfo {
+    #[serde(flatten)]
+    node: NeighborInfo,
+    error: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum InconsistencyInfo {
+    Cycles {
+        count: usize,
+    },
+    NotOnCycle {
+        node: NeighborInfo,
+    },
+    // the predecessor of the successor of the node is another node
+    Asymmetric {
+        node: NeighborInfo,
+        successor: NeighborInfo,
+        predecessor: Option<NeighborInfo>,
+    },
+    Unreachable {
+        node: NeighborInfo,
+    },
+}
+
+impl From<Inconsistency> for InconsistencyInfo {
+    fn from(i: Inconsistency) -> Self {
+        match i {
+            Inconsistency::Cycles(count) => InconsistencyInfo::Cycles { count },
+            Inconsistency::NotOnCycle(node) => InconsistencyInfo::NotOnCycle { node: node.into() },
+            Inconsistency::Asymmetric {
+                node,
+                successor,
+                predecessor,
+            } => InconsistencyInfo::Asymmetric {
+                node: node.into(),
+                successor: successor.into(),
+                predecessor: predecessor.map(NeighborInfo::from),
+            },
+            Inconsistency::Unreachable(node) => {
+                InconsistencyInfo::Unreachable { node: node.into() }
+            }
+        }
+    }
+}
+
+// walks the ring over the chord protocol and reports every node on it
+pub async fn ring(node: Arc<ChordNode>) -> Result<Response<String>, warp::Rejection> {
+    let b = Response::builder();
+    if node.is_crashed().await {
+        return Ok(b
+            .status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+            .body("oh no I crashed :(".to_string())
+            .unwrap());
+    }
+    let start = Instant::now();
+    let ring = node.ring().await;
+    let resp = RingResponse {
+        consistent: ring.is_consistent(),
+        nodes: ring
+            .nodes
+            .iter()
+            .map(|entry| RingNodeInfo {
+                node: entry.node.into(),
+                predecessor: entry.predecessor.map(NeighborInfo::from),
+                successors: entry.successors.iter().map(|&s| s.into()).collect(),
+                range_share: entry.range_share(),
+            })
+            .collect(),
+        unreachable: ring
+            .unreachable
+            .into_iter()
+            .map(|(node, error)| UnreachableInfo {
+                node: node.into(),
+                error,
+            })
+            .collect(),
+        cycles: ring
+            .cycles
+            .iter()
+            .map(|cycle| cycle.iter().map(|n| format!("{:x}", n.id)).collect())
+            .collect(),
+        inconsistencies: ring.inconsistencies.into_iter().map(Into::into).collect(),
+        total_ms: start.elapsed().as_secs_f64() * 1000.0,
+    };
+    Ok(b.header("content-type", "application/json")
+        .body(serde_json::to_string(&resp).unwrap())
+        .unwrap())
+}
+
+// time between the walks of the ring while waiting for it to become stable
+const STABLE_PERIOD: u64 = 200;
+// every walk asks all nodes, a request must not keep the node walking
+// the ring for long or all the time
+const MIN_STABLE_PERIOD: u64 = 50;
+const MAX_STABLE_WAIT: u64 = 5 * 60 * 1000;
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct StableQuery {
+    // milliseconds to wait for the ring to become stable, only checked once if not set
+    wait: Option<u64>,
+    // milliseconds between the checks while waiting
+    period: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StableResponse {
+    stable: bool,
+    // until the ring was stable or the wait gave up
+    elapsed_ms: f64,
+    checks: usize,
+    // nodes and cycles found by the last walk of the ring
+    nodes: usize,
+    cycles: usize,
+    inconsistencies: Vec<InconsistencyInfo>,
+}
+
+// reports whether the ring is consistent, waiting for it if asked to,
+// answers with 503 if it is not
+pub async fn stable(
+    node: Arc<ChordNode>,
+    query: StableQuery,
+) -> Result<Response<String>, warp::Rejection> {
+    let b = Response::builder();
+    let wait = query.wait.unwrap_or(0);
+    let period = query.period.unwrap_or(STABLE_PERIOD);
+    if wait > MAX_STABLE_WAIT || period < MIN_STABLE_PERIOD {
+        return Ok(b
+            .status(warp::http::StatusCode::BAD_REQUEST)
+            .body(format!(
+                "wait must be at most {} ms and period at least {} ms",
+                MAX_STABLE_WAIT, MIN_STABLE_PERIOD
+            ))
+            .unwrap());
+    }
+    if node.is_crashed().await {
+        return Ok(b
+            .status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+            .body("oh no I crashed :(".to_string())
+            .unwrap());
+    }
+    let result = node
+        .wait_until_stable(Duration::from_millis(wait), Duration::from_millis(period))
+        .await;
+    let resp = StableResponse {
+        stable: result.stable,
+        elapsed_ms: result.elapsed.as_secs_f64() * 1000.0,
+        checks: result.checks,
+        nodes: result.ring.nodes.len(),
+        cycles: result.ring.cycles.len(),
+        inconsistencies: result
+            .ring
+            .inconsistencies
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+    };
+    let status = if resp.stable {
+        warp::http::StatusCode::OK
+    } else {
+        warp::http::StatusCode::SERVICE_UNAVAILABLE
+    };
+    Ok(b.status(status)
+        .header("content-type", "application/json")
+        .body(serde_json::to_string(&resp).unwrap())
+        .unwrap())
+}
+
+#[derive(Serialize)]
+struct EventMessage {
+    // chord address of the node that saw the event
+    node: SocketAddr,
+    timestamp_ms: u64,
+    #[serde(flatten)]
+    event: EventInfo,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum EventInfo {
+    PredecessorChanged {
+        vnode: String,
+        old: Option<NeighborInfo>,
+        new: Option<NeighborInfo>,
+    },
+    SuccessorChanged {
+        vnode: String,
+        old: NeighborInfo,
+        new: NeighborInfo,
+    },
+    Failover {
+        vnode: String,
+        failed: NeighborInfo,
+        successor: NeighborInfo,
+    },
+    Joined {
+        entry: SocketAddr,
+    },
+    Left,
+    Crashed,
+    Recovered,
+    KeysMigrated {
+        from: SocketAddr,
+        to: SocketAddr,
+        keys: usize,
+    },
+    ReplicasPromoted {
+        vnode: String,
+        keys: usize,
+    },
+    // the subscriber was too slow and missed the given number of events
+    Lagged {
+        missed: u64,
+    },
+}
+
+impl From<Event> for EventInfo {
+    fn from(event: Event) -> Self {
+        match event {
+            Event::PredecessorChanged { vnode, old, new } => EventInfo::PredecessorChanged {
+                vnode: format!("{:x}", vnode),
+                old: old.map(NeighborInfo::from),
+                new: new.map(NeighborInfo::from),
+            },
+            Event::SuccessorChanged { vnode, old, new } => EventInfo::SuccessorChanged {
+                vnode: format!("{:x}", vnode),
+                old: old.into(),
+                new: new.into(),
+            },
+            Event::Failover {
+                vnode,
+                failed,
+                successor,
+            } => EventInfo::Failover {
+                vnode: format!("{:x}", vnode),
+                failed: failed.into(),
+                successor: successor.into(),
+            },
+            Event::Joined { entry } => EventInfo::Joined { entry },
+            Event::Left => EventInfo::Left,
+            Event::Crashed => EventInfo::Crashed,
+            Event::Recovered => EventInfo::Recovered,
+            Event::KeysMigrated { from, to, keys } => EventInfo::KeysMigrated { from, to, keys },
+            Event::ReplicasPromoted { vnode, keys } => EventInfo::ReplicasPromoted {
+                vnode: format!("{:x}", vnode),
+                keys,
+            },
+        }
+    }
+}
+
+// the events of the node as server-sent events, named by their kind
+fn event_stream(
+    node: Arc<ChordNode>,
+) -> impl Stream<Item = Result<warp::sse::Event, Infallible>> + Send + 'static {
+    let events = node.events.subscribe();
+    stream::unfold((node, events), |(node, mut events)| async move {
+        let (name, timestamp_ms, event) = match events.recv().await {
+            Ok(timed) => (timed.event.name(), timed.timestamp_ms, timed.event.into()),
+            // the events were missed, so the lag is only noticed now
+            Err(RecvError::Lagged(missed)) => ("lagged", now_ms(), EventInfo::Lagged { missed }),
+            Err(RecvError::Closed) => return None,
+        };
+        let message = EventMessage {
+            node: node.address,
+            timestamp_ms,
+            event,
+        };
+        let sse = warp::sse::Event::default()
+            .event(name)
+            .data(serde_json::to_string(&message).unwrap());
+        Some((Ok(sse), (node, events)))
+    })
+}
+
+// metrics of the node in the prometheus text format
+pub async fn metrics(node: Arc<ChordNode>) -> Result<Response<String>, warp::Rejection> {
+    Ok(Response::builder()
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(node.render_metrics().await)
+        .unwrap())
+}
+
+// label of a request in the request metrics, keys are left out
+// so that every route has only one label
+fn route_label(method: &warp::http::Method, path: &str) -> String {
+    const ROUTES: &[&str] = &[
+        "storage",
+        "node-info",
+        "trace",
+        "ring",
+        "stable",
+        "events",
+        "join",
+        "leave",
+        "sim-crash",
+        "sim-recover",
+        "metrics",
+    ];
+    let route = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    if ROUTES.contains(&route) {
+        format!("{:} /{:}", method, route)
+    } else {
+        "other".to_string()
+    }
 }
 
 #[derive(Deserialize, Clone, Copy)]
@@ -111,9 +662,32 @@ pub struct JoinRequest {
     nprime: SocketAddr,
 }
 
+// requests the info of another node, over https if tls is used
+async fn request_node_info(
+    addr: SocketAddr,
+    tls: Option<&TlsConfig>,
+) -> Result<Response<Body>, Box<dyn std::error::Error + Send + Sync>> {
+    match tls {
+        Some(tls) => {
+            let stream = tls.connect(addr, TcpStream::connect(addr).await?).await?;
+            let (mut sender, connection) = client::conn::handshake(stream).await?;
+            tokio::spawn(connection);
+            let req = Request::get("/node-info")
+                .header("host", addr.to_string())
+                .body(Body::empty())?;
+            Ok(sender.send_request(req).await?)
+        }
+        None => {
+            let url: Uri = format!("http://{:}/node-info", addr).parse()?;
+            Ok(Client::new().get(url).await?)
+        }
+    }
+}
+
 pub async fn join(
     node: Arc<ChordNode>,
     req: JoinRequest,
+    tls: Option<TlsConfig>,
 ) -> Result<Response<String>, warp::Rejection> {
     let b = Response::builder();
     if node.is_crashed().await {
@@ -122,15 +696,11 @@ pub async fn join(
             .body("oh no I crashed :(".to_string())
             .unwrap());
     }
-    let client = Client::new();
-
-    let url: Uri = format!("http://{:}/node-info", req.nprime).parse().unwrap();
-
     let ok: bool;
     let mut err_str: String = "".to_string();
 
     // TODO fix this ugly code
-    match client.get(url).await {
+    match request_node_info(req.nprime, tls.as_ref()).await {
         Ok(mut resp) => match to_bytes(resp.body_mut()).await {
             Ok(body_bytes) => match serde_json::from_slice::<InfoReponse>(&body_bytes) {
                 Ok(info) => {
@@ -157,12 +727,12 @@ pub async fn join(
         }
     }
     if ok {
-        println!("[{:}] joined chord network {:}", node.address, req.nprime);
+        info!(node = %node.address, entry = %req.nprime, "joined chord network");
         Ok(b.status(warp::http::StatusCode::OK)
             .body("ok".to_string())
             .unwrap())
     } else {
-        println!("[{:}] cannot join chord network {:}", node.address, err_str);
+        warn!(node = %node.address, error = %err_str, "cannot join chord network");
         Ok(b.status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
             .body("cannot join network".to_string())
             .unwrap())
@@ -179,12 +749,12 @@ pub async fn leave(node: Arc<ChordNode>) -> Result<Response<String>, warp::Rejec
     }
 
     if let Err(err) = node.leave().await {
-        eprintln!("[{:}] cannot leave network: {:?}", node.address, err);
+        warn!(node = %node.address, error = ?err, "cannot leave network");
         Ok(b.status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
             .body("cannot leave network".to_string())
             .unwrap())
     } else {
-        println!("[{:}] left chord network", node.address);
+        info!(node = %node.address, "left chord network");
         Ok(b.status(warp::http::StatusCode::OK)
             .body("ok".to_string())
             .unwrap())
@@ -200,15 +770,12 @@ pub async fn sim_crash(node: Arc<ChordNode>) -> Result<Response<String>, warp::R
             .unwrap());
     }
     if let Err(err) = node.sim_crash().await {
-        eprintln!(
-            "[{:}] error while simulating crash: {:?}",
-            node.address, err
-        );
+        warn!(node = %node.address, error = ?err, "error while simulating crash");
         Ok(b.status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
             .body("error while sim-crash".to_string())
             .unwrap())
     } else {
-        println!("[{:}] artificially crashed", node.address);
+        info!(node = %node.address, "artificially crashed");
         Ok(b.status(warp::http::StatusCode::OK)
             .body("ok".to_string())
             .unwrap())
@@ -217,38 +784,45 @@ pub async fn sim_crash(node: Arc<ChordNode>) -> Result<Response<String>, warp::R
 pub async fn sim_recover(node: Arc<ChordNode>) -> Result<Response<String>, warp::Rejection> {
     let b = Response::builder();
     if let Err(err) = node.sim_recover().await {
-        eprintln!(
-            "[{:}] error while simulating crash: {:?}",
-            node.address, err
-        );
+        warn!(node = %node.address, error = ?err, "error while simulating recovery");
         Ok(b.status(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
             .body("error while sim-recovering".to_string())
             .unwrap())
     } else {
-        println!(
-            "[{:}] can now try to reconnect to chord network",
-            node.address
-        );
+        info!(node = %node.address, "can now try to reconnect to chord network");
         Ok(b.status(warp::http::StatusCode::OK)
             .body("ok".to_string())
             .unwrap())
     }
 }
 
-pub async fn serve(addr: SocketAddr, node: Arc<ChordNode>) {
-    let storage_api = warp::path!("storage" / String);
+// serves the api over https if tls is used, clients need a certificate
+// signed by the ca since anyone else could change the ring and the keys
+pub async fn serve(addr: SocketAddr, node: Arc<ChordNode>, tls: Option<TlsConfig>) {
+    let storage_api = warp::path!("storage" / String).and(warp::query::<StorageQuery>());
     let get_chord_node = node.clone();
     // get items api
     let get = storage_api
         .and(warp::get())
-        .and_then(move |key| get(get_chord_node.clone(), key));
+        .and_then(move |key, query| get(get_chord_node.clone(), key, query));
 
     let put_chord_node = node.clone();
     // store items api
     let put = storage_api
         .and(warp::put())
+        .and(warp::header::optional::<String>("content-type"))
         .and(warp::body::bytes())
-        .and_then(move |key: String, value: Bytes| put(put_chord_node.clone(), key, value));
+        .and_then(
+            move |key: String, query: StorageQuery, content_type: Option<String>, value: Bytes| {
+                put(put_chord_node.clone(), key, query, content_type, value)
+            },
+        );
+
+    let delete_chord_node = node.clone();
+    // delete items api
+    let delete = storage_api
+        .and(warp::delete())
+        .and_then(move |key, query| delete(delete_chord_node.clone(), key, query));
 
     let info_chord_node = node.clone();
     let info = warp::path!("node-info")
@@ -256,9 +830,38 @@ pub async fn serve(addr: SocketAddr, node: Arc<ChordNode>) {
         .and_then(move || info(info_chord_node.clone()));
 
     let join_chord_node = node.clone();
+    let join_tls = tls.clone();
     let join = warp::path!("join")
         .and(warp::query())
-        .and_then(move |req: JoinRequest| join(join_chord_node.clone(), req));
+        .and_then(move |req: JoinRequest| join(join_chord_node.clone(), req, join_tls.clone()));
+
+    let trace_chord_node = node.clone();
+    let trace = warp::path!("trace" / String)
+        .and(warp::get())
+        .and(warp::query::<StorageQuery>())
+        .and_then(move |key, query| trace(trace_chord_node.clone(), key, query));
+
+    let ring_chord_node = node.clone();
+    let ring = warp::path!("ring")
+        .and(warp::get())
+        .and_then(move || ring(ring_chord_node.clone()));
+
+    let stable_chord_node = node.clone();
+    let stable = warp::path!("stable")
+        .and(warp::get())
+        .and(warp::query::<StableQuery>())
+        .and_then(move |query| stable(stable_chord_node.clone(), query));
+
+    let events_chord_node = node.clone();
+    let events = warp::path!("events").and(warp::get()).map(move || {
+        let stream = event_stream(events_chord_node.clone());
+        warp::sse::reply(warp::sse::keep_alive().stream(stream))
+    });
+
+    let metrics_chord_node = node.clone();
+    let metrics = warp::path!("metrics")
+        .and(warp::get())
+        .and_then(move || metrics(metrics_chord_node.clone()));
 
     let leave_chord_node = node.clone();
     let leave = warp::path!("leave").and_then(move || leave(leave_chord_node.clone()));
@@ -270,14 +873,36 @@ pub async fn serve(addr: SocketAddr, node: Arc<ChordNode>) {
     let sim_recover =
         warp::path!("sim-recover").and_then(move || sim_recover(sim_recover_node.clone()));
 
-    warp::serve(
+    let server = warp::serve(
         get.or(put)
+            .or(delete)
             .or(info)
+            .or(trace)
+            .or(ring)
+            .or(stable)
+            .or(events)
             .or(join)
             .or(leave)
             .or(sim_crash)
-            .or(sim_recover),
-    )
-    .bind(addr)
-    .await;
+            .or(sim_recover)
+            .or(metrics)
+            .with(warp::log::custom(move |info| {
+                node.metrics
+                    .request_duration
+                    .with_label(&route_label(info.method(), info.path()))
+                    .observe_duration(info.elapsed());
+            })),
+    );
+    match tls {
+        Some(tls) => {
+            server
+                .tls()
+                .cert_path(&tls.cert_path)
+                .key_path(&tls.key_path)
+                .client_auth_required_path(&tls.ca_path)
+                .bind(addr)
+                .await
+        }
+        None => server.bind(addr).await,
+    }
 }
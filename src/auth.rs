@@ -0,0 +1,107 @@
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// environment variable that contains the cluster key if no key file is given
+pub const CLUSTER_KEY_VAR: &str = "ACCORD_CLUSTER_KEY";
+
+// length of the nonce each side of a connection picks
+pub const NONCE_LEN: usize = 16;
+
+// secret shared by all nodes of a ring, every frame carries a mac computed
+// with it so that processes without the key can not send messages to the ring
+// the mac also covers the nonces of the connection, so a captured frame is
+// not accepted on any other connection
+// unlike tls this neither encrypts the messages nor stops someone who can
+// inject into an open connection from repeating frames on it
+#[derive(Clone)]
+pub struct ClusterKey {
+    key: Arc<Vec<u8>>,
+}
+
+impl ClusterKey {
+    pub fn new(key: &[u8]) -> io::Result<Self> {
+        if key.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cluster key is empty",
+            ));
+        }
+        Ok(ClusterKey {
+            key: Arc::new(key.to_vec()),
+        })
+    }
+
+    // reads the key from a file, a trailing newline is not part of the key
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let key = fs::read(path)?;
+        let len = key
+            .iter()
+            .rposition(|b| *b != b'\n' && *b != b'\r')
+            .map_or(0, |i| i + 1);
+        ClusterKey::new(&key[..len])
+    }
+
+    // reads the key from CLUSTER_KEY_VAR, returns None if it is not set
+    // a key that is not utf-8 is rejected instead of being replaced by a
+    // different one, keys with arbitrary bytes have to be given in a file
+    pub fn from_env() -> Option<io::Result<Self>> {
+        std::env::var_os(CLUSTER_KEY_VAR).map(|key| match key.into_string() {
+            Ok(key) => ClusterKey::new(key.as_bytes()),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not valid utf-8", CLUSTER_KEY_VAR),
+            )),
+        })
+    }
+
+    fn hmac(&self) -> HmacSha256 {
+        // hmac accepts keys of any length
+        HmacSha256::new_from_slice(&self.key).unwrap()
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let mut mac = self.hmac();
+        mac.update(data);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    // compares in constant time
+    pub fn verify(&self, data: &[u8], mac: &[u8]) -> bool {
+        let mut expected = self.hmac();
+        expected.update(data);
+        expected.verify(mac).is_ok()
+    }
+}
+
+// random bytes that both sides of a connection send in the handshake
+pub fn new_nonce() -> io::Result<Vec<u8>> {
+    let mut nonce = vec![0; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(io::Error::other)?;
+    Ok(nonce)
+}
+
+// the nonces of both sides of a connection, the mac of a frame covers the
+// nonce of its sender first so that it is not accepted in the other direction
+#[derive(Clone, Debug)]
+pub struct Nonces {
+    pub own: Vec<u8>,
+    pub peer: Vec<u8>,
+}
+
+impl Nonces {
+    // signed into the frames we send
+    pub fn sent(&self) -> Vec<u8> {
+        [&self.own[..], &self.peer[..]].concat()
+    }
+
+    // signed into the frames the peer sends
+    pub fn received(&self) -> Vec<u8> {
+        [&self.peer[..], &self.own[..]].concat()
+    }
+}
@@ -0,0 +1,102 @@
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+use crate::node::Neighbor;
+use crate::routing::id::Identifier;
+
+// events a subscriber can fall behind before it misses some
+const CAPACITY: usize = 1024;
+
+// a change of the ring as seen by a single node
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    PredecessorChanged {
+        vnode: Identifier,
+        old: Option<Neighbor>,
+        new: Option<Neighbor>,
+    },
+    SuccessorChanged {
+        vnode: Identifier,
+        old: Neighbor,
+        new: Neighbor,
+    },
+    // the successor did not answer and the first alive backup replaced it
+    Failover {
+        vnode: Identifier,
+        failed: Neighbor,
+        successor: Neighbor,
+    },
+    Joined {
+        entry: SocketAddr,
+    },
+    Left,
+    Crashed,
+    Recovered,
+    // keys that were pushed to another node on leave or handed off to their owner
+    KeysMigrated {
+        from: SocketAddr,
+        to: SocketAddr,
+        keys: usize,
+    },
+    // copies of keys of a failed predecessor became primaries
+    ReplicasPromoted {
+        vnode: Identifier,
+        keys: usize,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::PredecessorChanged { .. } => "predecessor_changed",
+            Event::SuccessorChanged { .. } => "successor_changed",
+            Event::Failover { .. } => "failover",
+            Event::Joined { .. } => "joined",
+            Event::Left => "left",
+            Event::Crashed => "crashed",
+            Event::Recovered => "recovered",
+            Event::KeysMigrated { .. } => "keys_migrated",
+            Event::ReplicasPromoted { .. } => "replicas_promoted",
+        }
+    }
+}
+
+// an event together with the time it was emitted, in milliseconds since
+// the unix epoch
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedEvent {
+    pub timestamp_ms: u64,
+    pub event: Event,
+}
+
+// passes the events of a node on to everyone subscribed at the time,
+// events are dropped while nobody is subscribed
+pub struct Events(broadcast::Sender<TimedEvent>);
+
+impl Default for Events {
+    fn default() -> Self {
+        Events(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl Events {
+    pub fn emit(&self, event: Event) {
+        let _ = self.0.send(TimedEvent {
+            timestamp_ms: now_ms(),
+            event,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<TimedEvent> {
+        self.0.subscribe()
+    }
+}
+
+// milliseconds since the unix epoch
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
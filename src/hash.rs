@@ -1,7 +1,7 @@
 // A small tool used to calculate the hashes within the python script
 // to calculate the predecessors and succesors for each node
 
-use accord::routing::id::HashIdentifier;
+use accord::routing::id::{self, HashFunction, HashIdentifier, IdSpace};
 use std::net::SocketAddr;
 use structopt::StructOpt;
 
@@ -10,10 +10,29 @@ use structopt::StructOpt;
 struct Opt {
     #[structopt(name = "adress", help = "address and port")]
     address: SocketAddr,
+
+    #[structopt(
+        long,
+        default_value = "64",
+        parse(try_from_str = id::parse_bits),
+        help = "width (bits) of the identifiers"
+    )]
+    id_bits: u32,
+
+    #[structopt(
+        long,
+        default_value = "sha256",
+        help = "hash function the identifiers are derived with"
+    )]
+    id_hash: HashFunction,
 }
 
 #[tokio::main]
 async fn main() {
     let opt = Opt::from_args();
-    println!("{:}", opt.address.hash_id());
+    let space = IdSpace {
+        bits: opt.id_bits,
+        hash: opt.id_hash,
+    };
+    println!("{:}", opt.address.hash_id(&space));
 }
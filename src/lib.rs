@@ -1,4 +1,12 @@
 pub mod api;
+pub mod auth;
+pub mod events;
+pub mod logging;
+pub mod metrics;
 pub mod network;
 pub mod node;
+pub mod ring;
 pub mod routing;
+pub mod storage;
+pub mod tls;
+pub mod transport;
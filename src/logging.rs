@@ -0,0 +1,41 @@
+use std::io::IsTerminal;
+use std::str::FromStr;
+use tracing_subscriber::EnvFilter;
+
+// variable that selects what is logged, e.g. ACCORD_LOG=debug or
+// ACCORD_LOG=info,accord::network=debug
+pub const FILTER_VAR: &str = "ACCORD_LOG";
+// logged if the variable is not set
+const DEFAULT_FILTER: &str = "info";
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LogFormat {
+    // human readable lines
+    Text,
+    // one json object per line, including the fields of all enclosing spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {:}", s)),
+        }
+    }
+}
+
+// installs the global subscriber, logs are written to stdout
+pub fn init(format: LogFormat) {
+    let filter =
+        EnvFilter::try_from_env(FILTER_VAR).unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        // colors only end up as escape codes in files
+        LogFormat::Text => builder.with_ansi(std::io::stdout().is_terminal()).init(),
+        LogFormat::Json => builder.json().with_current_span(false).init(),
+    }
+}
@@ -1,14 +1,26 @@
 use accord::api;
-use accord::network::Message;
+use accord::auth::ClusterKey;
+use accord::logging::{self, LogFormat};
+use accord::network::{self, NetworkConfig};
+use accord::node::{LookupMode, NodeConfig};
+use accord::routing::id::{self, HashFunction, IdSpace};
+use accord::storage::DiskStorage;
+use accord::tls::TlsConfig;
 
-use std::{net::SocketAddr, sync::Arc};
-use structopt::StructOpt;
+use std::{
+    collections::HashMap,
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use structopt::{clap, StructOpt};
 
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
     net::TcpListener,
     time::{sleep, Duration},
 };
+use tracing::{error, info, warn};
 #[derive(StructOpt)]
 #[structopt(name = "akkord", about = "Chord Node Process")]
 struct Opt {
@@ -25,54 +37,279 @@ struct Opt {
     )]
     stabilization_period: u64,
 
+    #[structopt(
+        long,
+        default_value = "3",
+        parse(try_from_str = parse_successor_list_length),
+        help = "number of successors each node keeps track of, at least 2 so that a failed successor can be replaced"
+    )]
+    successor_list_length: usize,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        parse(try_from_str = parse_replication_factor),
+        help = "number of nodes that store a copy of each key, at most the successor list length"
+    )]
+    replication_factor: usize,
+
+    #[structopt(
+        long,
+        default_value = "1",
+        parse(try_from_str = parse_vnodes),
+        help = "number of positions the node takes on the ring, at least 1"
+    )]
+    vnodes: usize,
+
+    #[structopt(
+        long,
+        default_value = "64",
+        parse(try_from_str = id::parse_bits),
+        help = "width (bits) of the identifiers, one of 64, 128 and 160"
+    )]
+    id_bits: u32,
+
+    #[structopt(
+        long,
+        default_value = "sha256",
+        help = "hash function the identifiers are derived with, one of sha1, sha256 and blake3"
+    )]
+    id_hash: HashFunction,
+
+    #[structopt(
+        long,
+        default_value = "500",
+        help = "duration (milliseconds) the predecessor has to answer a ping"
+    )]
+    ping_timeout: u64,
+
+    #[structopt(
+        long,
+        default_value = "recursive",
+        help = "how the owner of a key is found unless the request chooses, recursive or iterative"
+    )]
+    lookup_mode: LookupMode,
+
+    #[structopt(
+        long,
+        default_value = "1000",
+        help = "duration (milliseconds) each hop of an iterative lookup has to answer"
+    )]
+    hop_timeout: u64,
+
+    #[structopt(
+        long,
+        default_value = "500",
+        help = "duration (milliseconds) to wait for a connection to another node"
+    )]
+    connect_timeout: u64,
+
+    #[structopt(
+        long,
+        default_value = "500",
+        help = "duration (milliseconds) to wait for a message to be sent"
+    )]
+    write_timeout: u64,
+
+    #[structopt(
+        long,
+        default_value = "5000",
+        help = "duration (milliseconds) to wait for a response"
+    )]
+    read_timeout: u64,
+
+    #[structopt(
+        long,
+        default_value = "2",
+        help = "number of retries for idempotent messages"
+    )]
+    retries: u32,
+
+    #[structopt(
+        long,
+        default_value = "50",
+        help = "duration (milliseconds) before the first retry, doubled after each attempt"
+    )]
+    retry_backoff: u64,
+
     #[structopt(
         long,
         default_value = "10",
         help = "number of minutes after the node will kill itself"
     )]
     ttl: u64,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "directory to persist keys in (keys are only kept in memory if not set)"
+    )]
+    data_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "30",
+        help = "duration (seconds) between snapshots of the persisted keys"
+    )]
+    snapshot_period: u64,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires_all = &["tls-key", "tls-ca"],
+        help = "certificate (pem) for tls on the chord and web server, chord and web traffic is plaintext if not set"
+    )]
+    tls_cert: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "tls-cert",
+        help = "private key (pem) of the tls certificate"
+    )]
+    tls_key: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        requires = "tls-cert",
+        help = "ca certificates (pem) that other nodes and web clients need to be signed by"
+    )]
+    tls_ca: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "file with the secret that all chord messages are signed with, read from the ACCORD_CLUSTER_KEY environment variable if not set"
+    )]
+    cluster_key_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "text",
+        help = "format of the logs, text or json, what is logged is set by the ACCORD_LOG environment variable (default info)"
+    )]
+    log_format: LogFormat,
+}
+
+// file in the data directory that contains the last known successors
+// which are used to rejoin the ring after a restart
+const RING_FILE: &str = "ring.json";
+
+fn read_known_nodes(data_dir: &Path) -> Vec<SocketAddr> {
+    fs::read(data_dir.join(RING_FILE))
+        .ok()
+        .and_then(|buf| serde_json::from_slice(&buf).ok())
+        .unwrap_or_default()
+}
+
+fn parse_successor_list_length(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n >= 2 => Ok(n),
+        Ok(_) => Err("the successor list needs at least 2 entries".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_replication_factor(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(n),
+        Ok(_) => Err("every key needs at least 1 copy".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn parse_vnodes(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n >= 1 => Ok(n),
+        Ok(_) => Err("the node needs at least 1 position on the ring".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+fn write_known_nodes(data_dir: &Path, nodes: &[SocketAddr]) -> std::io::Result<()> {
+    fs::write(data_dir.join(RING_FILE), serde_json::to_vec(nodes)?)
 }
 
 #[tokio::main]
 async fn main() {
     let opt = Opt::from_args();
+    // replicas are placed on the successor list
+    if opt.replication_factor > opt.successor_list_length {
+        clap::Error::with_description(
+            "--replication-factor can not be larger than --successor-list-length",
+            clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    logging::init(opt.log_format);
 
-    let chord_node = Arc::new(api::ChordNode::new(opt.address, opt.webserver_adress));
-    println!("[{:}] creating new chord network", chord_node.address);
-
-    let listener = TcpListener::bind(opt.address).await.unwrap();
-    let chord_server = async {
-        loop {
-            if chord_node.is_crashed().await {
-                listener.accept().await.unwrap();
-                continue;
-            }
-            let (mut tcp_stream, _) = listener.accept().await.unwrap();
-
-            let tcp_chord_node = chord_node.clone();
-            tokio::spawn(async move {
-                let mut send_buf = Vec::with_capacity(32);
-                tcp_stream.read_to_end(&mut send_buf).await.unwrap();
-                let msg: Message = serde_cbor::from_slice(send_buf.as_slice()).unwrap();
-
-                match tcp_chord_node.handle_message(msg).await {
-                    Ok(response) => {
-                        if let Some(resp) = response {
-                            let buf = serde_cbor::to_vec(&resp).unwrap();
-                            tcp_stream.write_all(&buf).await.unwrap();
-                            tcp_stream.shutdown().await.unwrap();
-                        }
-                    }
-                    Err(err) => {
-                        eprintln!(
-                            "[{:}] error handling message {:?}: {:?}",
-                            tcp_chord_node.address, msg, err
-                        );
-                    }
-                }
-            });
+    let config = NodeConfig {
+        successor_list_length: opt.successor_list_length,
+        replication_factor: opt.replication_factor,
+        ping_timeout: Duration::from_millis(opt.ping_timeout),
+        vnodes: opt.vnodes,
+        lookup_mode: opt.lookup_mode,
+        hop_timeout: Duration::from_millis(opt.hop_timeout),
+        network: NetworkConfig {
+            connect_timeout: Duration::from_millis(opt.connect_timeout),
+            write_timeout: Duration::from_millis(opt.write_timeout),
+            read_timeout: Duration::from_millis(opt.read_timeout),
+            retries: opt.retries,
+            retry_backoff: Duration::from_millis(opt.retry_backoff),
+            id_space: IdSpace {
+                bits: opt.id_bits,
+                hash: opt.id_hash,
+            },
+        },
+    };
+    let tls = match (&opt.tls_cert, &opt.tls_key, &opt.tls_ca) {
+        (Some(cert), Some(key), Some(ca)) => Some(TlsConfig::load(cert, key, ca).unwrap()),
+        _ => None,
+    };
+    let key = match &opt.cluster_key_file {
+        Some(path) => Some(ClusterKey::from_file(path).unwrap()),
+        None => ClusterKey::from_env().map(|key| key.unwrap()),
+    };
+    let chord_node = match &opt.data_dir {
+        Some(data_dir) => {
+            let store = DiskStorage::open(data_dir.join("store")).unwrap();
+            let replicas = DiskStorage::open(data_dir.join("replicas")).unwrap();
+            Arc::new(api::ChordNode::with_storage(
+                opt.address,
+                opt.webserver_adress,
+                config,
+                Box::new(store),
+                Box::new(replicas),
+                tls.clone(),
+                key.clone(),
+            ))
         }
+        None => Arc::new(api::ChordNode::with_storage(
+            opt.address,
+            opt.webserver_adress,
+            config,
+            Box::new(HashMap::new()),
+            Box::new(HashMap::new()),
+            tls.clone(),
+            key.clone(),
+        )),
     };
+    info!(node = %chord_node.address, "creating new chord network");
+
+    let listener = TcpListener::bind(opt.address).await.unwrap();
+    let handler_node = chord_node.clone();
+    let chord_server = network::listen_for_messages(
+        listener,
+        tls.clone(),
+        key.clone(),
+        chord_node.config.network,
+        chord_node.metrics.clone(),
+        move |msg, receiver| {
+            let node = handler_node.clone();
+            async move { node.handle_request(msg, receiver).await }
+        },
+    );
 
     let periodic_node = chord_node.clone();
     let stabilizer_task = async {
@@ -82,36 +319,89 @@ async fn main() {
                 let stabilization_node = periodic_node.clone();
                 tokio::spawn(async move {
                     if let Err(err) = stabilization_node.stabilize().await {
-                        println!(
-                            "[{:}] error while stabilizing: {:?}",
-                            stabilization_node.address, err
-                        );
+                        warn!(node = %stabilization_node.address, error = ?err, "error while stabilizing");
                     }
                 });
                 let check_node = periodic_node.clone();
                 tokio::spawn(async move {
-                    check_node.check_successors().await;
+                    if let Err(err) = check_node.check_successors().await {
+                        warn!(node = %check_node.address, error = ?err, "error while checking successors");
+                    }
+                });
+                let predecessor_node = periodic_node.clone();
+                tokio::spawn(async move {
+                    predecessor_node.check_predecessor().await;
+                });
+                let finger_node = periodic_node.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = finger_node.fix_fingers().await {
+                        warn!(node = %finger_node.address, error = ?err, "error while fixing fingers");
+                    }
                 });
             }
         }
     };
 
+    let snapshot_node = chord_node.clone();
+    let snapshot_task = async {
+        let data_dir = match &opt.data_dir {
+            Some(dir) => dir,
+            None => return futures::future::pending().await,
+        };
+        // try to rejoin the ring through the nodes known before the restart
+        for addr in read_known_nodes(data_dir) {
+            match snapshot_node.join(addr).await {
+                Ok(_) => {
+                    info!(node = %snapshot_node.address, entry = %addr, "rejoined chord network");
+                    break;
+                }
+                Err(err) => warn!(
+                    node = %snapshot_node.address,
+                    entry = %addr,
+                    error = ?err,
+                    "cannot rejoin chord network"
+                ),
+            }
+        }
+        loop {
+            sleep(Duration::from_secs(opt.snapshot_period)).await;
+            if let Err(err) = snapshot_node.snapshot().await {
+                error!(node = %snapshot_node.address, error = ?err, "error while taking snapshot");
+            }
+            let known_nodes: Vec<SocketAddr> = snapshot_node
+                .successors()
+                .await
+                .iter()
+                .map(|n| n.addr)
+                .filter(|addr| *addr != snapshot_node.address)
+                .collect();
+            if !known_nodes.is_empty() {
+                if let Err(err) = write_known_nodes(data_dir, &known_nodes) {
+                    error!(node = %snapshot_node.address, error = ?err, "error while saving known nodes");
+                }
+            }
+        }
+    };
+
     let api_node = chord_node.clone();
-    let webserver = api::serve(opt.webserver_adress, api_node);
+    let webserver = api::serve(opt.webserver_adress, api_node, tls.clone());
 
     tokio::select! {
-        val = chord_server => {
-            println!("chord server shut down: {:?}",val);
+        _ = chord_server => {
+            error!("chord server shut down");
         },
         val = webserver => {
-            println!("webserver shut down: {:?}",val);
+            error!(result = ?val, "webserver shut down");
         },
         val = stabilizer_task => {
-            println!("stabilizer shut down: {:?}",val);
+            error!(result = ?val, "stabilizer shut down");
+        },
+        val = snapshot_task => {
+            error!(result = ?val, "snapshot task shut down");
         },
         _ = sleep(Duration::from_secs(opt.ttl * 60)) => {
             // kill process after some time
-            println!("[{:}] suicide!",chord_node.address);
+            info!(node = %chord_node.address, "suicide!");
         },
     }
 }
@@ -0,0 +1,306 @@
+use futures::future::BoxFuture;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::network::{Message, MessageError};
+use crate::routing::id::Identifier;
+use crate::transport::Transport;
+
+// bounds of the buckets of durations in seconds
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+// bounds of the buckets of the number of hops of a lookup
+const HOP_BUCKETS: &[f64] = &[0.0, 1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0, 24.0, 32.0];
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Histogram {
+    bounds: &'static [f64],
+    // observations per bucket, the last one has no upper bound
+    buckets: Vec<AtomicU64>,
+    // sum of all observations as bits of a f64
+    sum: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits()),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sum| {
+                Some((f64::from_bits(sum) + value).to_bits())
+            });
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    // writes the buckets, sum and count with the given labels
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = self
+                .bounds
+                .get(i)
+                .map_or("+Inf".to_string(), |b| b.to_string());
+            let sep = if labels.is_empty() { "" } else { "," };
+            let _ = writeln!(
+                out,
+                "{:}_bucket{{{:}{:}le=\"{:}\"}} {:}",
+                name, labels, sep, le, cumulative
+            );
+        }
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{:}}}", labels)
+        };
+        let sum = f64::from_bits(self.sum.load(Ordering::Relaxed));
+        let _ = writeln!(out, "{:}_sum{:} {:}", name, labels, sum);
+        let _ = writeln!(out, "{:}_count{:} {:}", name, labels, self.count());
+    }
+}
+
+// counters that are told apart by the value of a single label
+pub struct CounterVec {
+    label: &'static str,
+    values: Mutex<BTreeMap<String, u64>>,
+}
+
+impl CounterVec {
+    fn new(label: &'static str) -> Self {
+        CounterVec {
+            label,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, value: &str) {
+        *self
+            .values
+            .lock()
+            .unwrap()
+            .entry(value.to_string())
+            .or_insert(0) += 1;
+    }
+
+    pub fn get(&self, value: &str) -> u64 {
+        self.values.lock().unwrap().get(value).copied().unwrap_or(0)
+    }
+}
+
+// histograms that are told apart by the value of a single label
+pub struct HistogramVec {
+    label: &'static str,
+    bounds: &'static [f64],
+    values: Mutex<BTreeMap<String, Arc<Histogram>>>,
+}
+
+impl HistogramVec {
+    fn new(label: &'static str, bounds: &'static [f64]) -> Self {
+        HistogramVec {
+            label,
+            bounds,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn with_label(&self, value: &str) -> Arc<Histogram> {
+        self.values
+            .lock()
+            .unwrap()
+            .entry(value.to_string())
+            .or_insert_with(|| Arc::new(Histogram::new(self.bounds)))
+            .clone()
+    }
+}
+
+// everything a node counts, served in the prometheus text format
+pub struct Metrics {
+    // nodes contacted to find the owner of a key
+    pub lookup_hops: Histogram,
+    // duration of the requests to the web server by route
+    pub request_duration: HistogramVec,
+    pub stabilization_duration: Histogram,
+    pub stabilization_errors: Counter,
+    pub predecessor_changes: Counter,
+    pub successor_changes: Counter,
+    // tasks that panicked while serving a connection or handling a request
+    pub panics: Counter,
+    // chord messages by variant
+    pub messages_sent: CounterVec,
+    pub messages_received: CounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            lookup_hops: Histogram::new(HOP_BUCKETS),
+            request_duration: HistogramVec::new("route", DURATION_BUCKETS),
+            stabilization_duration: Histogram::new(DURATION_BUCKETS),
+            stabilization_errors: Counter::default(),
+            predecessor_changes: Counter::default(),
+            successor_changes: Counter::default(),
+            panics: Counter::default(),
+            messages_sent: CounterVec::new("message"),
+            messages_received: CounterVec::new("message"),
+        }
+    }
+}
+
+impl Metrics {
+    // renders all metrics followed by the gauges, which are
+    // read from the node at the time of the request
+    pub fn render(&self, gauges: &[(&str, &str, f64)]) -> String {
+        let mut out = String::new();
+        header(
+            &mut out,
+            "accord_lookup_hops",
+            "nodes contacted to find the owner of a key",
+            "histogram",
+        );
+        self.lookup_hops.render(&mut out, "accord_lookup_hops", "");
+
+        let name = "accord_http_request_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "duration of requests to the web server",
+            "histogram",
+        );
+        for (value, histogram) in self.request_duration.values.lock().unwrap().iter() {
+            let labels = format!("{:}=\"{:}\"", self.request_duration.label, value);
+            histogram.render(&mut out, name, &labels);
+        }
+
+        let name = "accord_stabilization_duration_seconds";
+        header(
+            &mut out,
+            name,
+            "duration of stabilization runs",
+            "histogram",
+        );
+        self.stabilization_duration.render(&mut out, name, "");
+
+        for (name, help, counter) in [
+            (
+                "accord_stabilization_errors_total",
+                "stabilization runs that failed",
+                &self.stabilization_errors,
+            ),
+            (
+                "accord_predecessor_changes_total",
+                "changes of the predecessor of a virtual node",
+                &self.predecessor_changes,
+            ),
+            (
+                "accord_successor_changes_total",
+                "changes of the successor of a virtual node",
+                &self.successor_changes,
+            ),
+            (
+                "accord_panics_total",
+                "tasks that panicked while serving a connection or handling a request",
+                &self.panics,
+            ),
+        ] {
+            header(&mut out, name, help, "counter");
+            let _ = writeln!(out, "{:} {:}", name, counter.get());
+        }
+
+        for (name, help, counters) in [
+            (
+                "accord_messages_sent_total",
+                "chord messages sent by variant",
+                &self.messages_sent,
+            ),
+            (
+                "accord_messages_received_total",
+                "chord messages received by variant",
+                &self.messages_received,
+            ),
+        ] {
+            header(&mut out, name, help, "counter");
+            for (value, count) in counters.values.lock().unwrap().iter() {
+                let _ = writeln!(
+                    out,
+                    "{:}{{{:}=\"{:}\"}} {:}",
+                    name, counters.label, value, count
+                );
+            }
+        }
+
+        for (name, help, value) in gauges {
+            header(&mut out, name, help, "gauge");
+            let _ = writeln!(out, "{:} {:}", name, value);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {:} {:}", name, help);
+    let _ = writeln!(out, "# TYPE {:} {:}", name, kind);
+}
+
+// counts the messages sent through another transport
+pub struct MeteredTransport {
+    inner: Arc<dyn Transport>,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredTransport {
+    pub fn new(inner: Arc<dyn Transport>, metrics: Arc<Metrics>) -> Self {
+        MeteredTransport { inner, metrics }
+    }
+}
+
+impl Transport for MeteredTransport {
+    fn send(
+        &self,
+        msg: Message,
+        addr: SocketAddr,
+        receiver: Option<Identifier>,
+    ) -> BoxFuture<'_, Result<Option<Message>, MessageError>> {
+        self.metrics.messages_sent.inc(msg.name());
+        self.inner.send(msg, addr, receiver)
+    }
+}
@@ -1,17 +1,37 @@
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use std::collections::HashMap;
 use std::future::Future;
+use std::io;
 use std::net::SocketAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
-use warp::http;
+use tokio::sync::{oneshot, Mutex};
+use tokio::time::{sleep, timeout};
+use tracing::{debug, error, info_span, warn, Instrument};
 
-use crate::node::Neighbor;
-use crate::routing::id::Identifier;
+use crate::auth::{self, ClusterKey, Nonces};
+use crate::metrics::Metrics;
+use crate::node::{Hop, Neighbor, TraceHop};
+use crate::routing::id::{IdSpace, Identifier};
+use crate::tls::TlsConfig;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
-    Lookup(Identifier),
-    LookupResult(Neighbor),
+    // finds the successor of the identifier, the result contains the number
+    // of nodes the lookup was forwarded to and the nodes if the flag is set
+    Lookup(Identifier, bool),
+    LookupResult(Neighbor, u32, Option<Vec<TraceHop>>),
+
+    // a single step of an iterative lookup, asks for the owner of the
+    // identifier or the nodes that are closer to it
+    ClosestPreceding(Identifier),
+    ClosestPrecedingResult(Hop),
 
     GetPredecessor,
     PredecessorResponse(Option<Neighbor>),
@@ -19,6 +39,10 @@ pub enum Message {
     GetSuccessor,
     SuccessorResponse(Neighbor),
 
+    // returns the successor list of a node, starting with its direct successor
+    GetSuccessorList,
+    SuccessorListResponse(Vec<Neighbor>),
+
     // message sent to successor when a node leaves
     LeavePredecessor(Option<Neighbor>),
     // message sent to predecessor when a node leaves
@@ -26,17 +50,166 @@ pub enum Message {
 
     Notify(Neighbor),
 
+    // key migration
+    // hand over entries to their owner, on leave or once it joined
+    PushKeys(Vec<Entry>),
+    // store copies of entries owned by another node, answered with KeysAck
+    Replicate(Vec<Entry>),
+    // delete the copies of the given keys, sent by the owner to nodes
+    // that should no longer hold them
+    DropReplicas(Vec<String>),
+    // acknowledges that the given keys were stored by the receiver
+    // and can be deleted by the sender
+    KeysAck(Vec<String>),
+
     Ping,
     Pong,
+
+    // storage requests forwarded to the node responsible for a key
+    // keys are sent as strings and values in their binary encoding
+    Get(String),
+    GetResult(Result<Option<ByteBuf>, RemoteError>),
+    Put(String, ByteBuf),
+    PutAck(Result<(), RemoteError>),
+    Delete(String),
+    // contains whether the key existed before
+    DeleteAck(Result<bool, RemoteError>),
+
+    // sent instead of a response if the request could not be handled
+    Error(RemoteError),
+}
+
+// a stored key-value pair as it is sent between nodes
+// the key is sent as string and the value in its binary encoding,
+// a missing value marks a deleted key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub key: String,
+    pub version: u64,
+    pub value: Option<ByteBuf>,
+}
+
+impl Message {
+    // name of the variant, used to count messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::Lookup(..) => "Lookup",
+            Message::LookupResult(..) => "LookupResult",
+            Message::ClosestPreceding(..) => "ClosestPreceding",
+            Message::ClosestPrecedingResult(..) => "ClosestPrecedingResult",
+            Message::GetPredecessor => "GetPredecessor",
+            Message::PredecessorResponse(..) => "PredecessorResponse",
+            Message::GetSuccessor => "GetSuccessor",
+            Message::SuccessorResponse(..) => "SuccessorResponse",
+            Message::GetSuccessorList => "GetSuccessorList",
+            Message::SuccessorListResponse(..) => "SuccessorListResponse",
+            Message::LeavePredecessor(..) => "LeavePredecessor",
+            Message::LeaveSuccessor(..) => "LeaveSuccessor",
+            Message::Notify(..) => "Notify",
+            Message::PushKeys(..) => "PushKeys",
+            Message::Replicate(..) => "Replicate",
+            Message::DropReplicas(..) => "DropReplicas",
+            Message::KeysAck(..) => "KeysAck",
+            Message::Ping => "Ping",
+            Message::Pong => "Pong",
+            Message::Get(..) => "Get",
+            Message::GetResult(..) => "GetResult",
+            Message::Put(..) => "Put",
+            Message::PutAck(..) => "PutAck",
+            Message::Delete(..) => "Delete",
+            Message::DeleteAck(..) => "DeleteAck",
+            Message::Error(..) => "Error",
+        }
+    }
+
+    // messages that can be sent again without changing the outcome
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            Message::Lookup(_, _)
+                | Message::ClosestPreceding(_)
+                | Message::GetPredecessor
+                | Message::GetSuccessor
+                | Message::GetSuccessorList
+                | Message::Notify(_)
+                | Message::Replicate(_)
+                | Message::DropReplicas(_)
+                | Message::Ping
+                | Message::Get(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NetworkConfig {
+    pub connect_timeout: Duration,
+    pub write_timeout: Duration,
+    pub read_timeout: Duration,
+    // number of times an idempotent message is resent after a failure
+    pub retries: u32,
+    // delay before the first retry, doubled for every following one
+    pub retry_backoff: Duration,
+    // width and hash function of the identifiers, nodes refuse
+    // connections from rings that use other ones
+    pub id_space: IdSpace,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            connect_timeout: Duration::from_millis(500),
+            write_timeout: Duration::from_millis(500),
+            read_timeout: Duration::from_secs(5),
+            retries: 2,
+            retry_backoff: Duration::from_millis(50),
+            id_space: IdSpace::default(),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum MessageError {
     IOError(std::io::Error),
     SerdeError(serde_cbor::Error),
-    UnexpectedResponse(Message, Option<Message>),
+    UnexpectedResponse(Box<Message>, Option<Box<Message>>),
     AllSuccessorsDead(SocketAddr),
-    HTTPStatusError(http::StatusCode),
+    InvalidData(String),
+    Timeout(SocketAddr),
+    // the receiver of a request could not handle it
+    Remote(RemoteError),
+    // the peer does not support any of our protocol versions,
+    // contains the versions it supports
+    IncompatibleVersion(SocketAddr, Vec<u32>),
+    // the peer belongs to a ring with other identifiers,
+    // contains the identifier space of the peer
+    IncompatibleIdSpace(SocketAddr, IdSpace),
+    // no hop of an iterative lookup for the identifier could be reached
+    LookupFailed(Identifier),
+}
+
+// error that is sent back to the node that made a request
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RemoteError {
+    // the key or value could not be parsed by the receiver
+    InvalidData(String),
+    // the receiver failed to store the value or to forward the request
+    Failed(String),
+    // the request could not be decoded
+    Malformed(String),
+    // the receiver does not expect this kind of message as request
+    Unexpected(String),
+    // the message was sent with a version (first) other than the one
+    // agreed on for the connection (second)
+    UnsupportedVersion(u32, u32),
+    // the frame did not carry a valid mac for the cluster key
+    Unauthenticated,
+}
+
+impl MessageError {
+    // errors that might go away when the message is sent again
+    fn is_transient(&self) -> bool {
+        matches!(self, MessageError::IOError(_) | MessageError::Timeout(_))
+    }
 }
 
 impl From<std::io::Error> for MessageError {
@@ -49,43 +222,723 @@ impl From<serde_cbor::Error> for MessageError {
         MessageError::SerdeError(err)
     }
 }
+impl From<MessageError> for RemoteError {
+    fn from(err: MessageError) -> Self {
+        match err {
+            MessageError::Remote(err) => err,
+            MessageError::InvalidData(msg) => RemoteError::InvalidData(msg),
+            err => RemoteError::Failed(format!("{:?}", err)),
+        }
+    }
+}
 
-pub async fn send_message(msg: Message, addr: SocketAddr) -> Result<Option<Message>, MessageError> {
-    let mut stream = TcpStream::connect(addr).await?;
-    // send message
-    let buf = serde_cbor::to_vec(&msg)?;
-    stream.write_all(&buf).await?;
-    stream.shutdown().await?;
+// version of the chord protocol, increased whenever the encoding of
+// messages changes in an incompatible way after a release
+pub const PROTOCOL_VERSION: u32 = 1;
+// all versions this node can talk, a connection uses the newest version
+// both sides support
+pub const SUPPORTED_VERSIONS: &[u32] = &[PROTOCOL_VERSION];
 
-    // read response
-    let mut resp_buf = Vec::with_capacity(32);
-    stream.read_to_end(&mut resp_buf).await?;
-    if resp_buf.is_empty() {
-        Ok(None)
-    } else {
-        let answer: Message = serde_cbor::from_slice(resp_buf.as_slice())?;
-        Ok(Some(answer))
+// every frame starts with its length as big endian u32
+// larger frames are rejected to protect against broken peers
+const MAX_FRAME_LEN: u32 = 64 * 1024 * 1024;
+// limit of the handshake frames, which are read before the peer is known
+const MAX_HANDSHAKE_LEN: u32 = 4 * 1024;
+
+// first frame in both directions of every connection, the client lists
+// the versions it supports and the server picks one or rejects the client
+// the hello is the same in all versions so that every client gets an answer
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Handshake {
+    Hello(Vec<u32>),
+    Accept(u32),
+    Reject(Vec<u32>),
+    // identifier space of the sender, sent by both sides once version 2 or
+    // later is agreed on, the server closes the connection if they differ
+    Space(IdSpace),
+    // random bytes of the sender, sent by both sides after the identifier
+    // space from version 4 on
+    Nonce(ByteBuf),
+}
+
+// reads the next handshake frame
+async fn read_handshake<R>(reader: &mut R) -> Result<Handshake, MessageError>
+where
+    R: AsyncRead + Unpin,
+{
+    match read_frame_limited(reader, MAX_HANDSHAKE_LEN).await? {
+        Some(buf) => Ok(serde_cbor::from_slice(&buf)?),
+        None => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
     }
 }
 
-pub async fn listen_for_messages<F, Fut>(addr: SocketAddr, handler: F) -> Result<(), MessageError>
+fn unexpected_handshake(handshake: Handshake) -> MessageError {
+    MessageError::InvalidData(format!("unexpected handshake {:?}", handshake))
+}
+
+// reads the nonce of the peer
+async fn read_nonce<R>(reader: &mut R) -> Result<Vec<u8>, MessageError>
 where
-    F: Fn(Message) -> Fut,
-    Fut: Future<Output = Option<Message>>,
+    R: AsyncRead + Unpin,
 {
-    let listener = TcpListener::bind(addr).await?;
+    match read_handshake(reader).await? {
+        Handshake::Nonce(nonce) if nonce.len() == auth::NONCE_LEN => Ok(nonce.into_vec()),
+        other => Err(unexpected_handshake(other)),
+    }
+}
+
+// picks the newest version supported by both sides
+pub fn negotiate_version(versions: &[u32]) -> Option<u32> {
+    SUPPORTED_VERSIONS
+        .iter()
+        .filter(|v| versions.contains(v))
+        .max()
+        .copied()
+}
+
+// frame that carries a message after the handshake
+// the response to a request carries the id of the request so that many
+// requests can share a connection
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+    // protocol version the message is encoded with
+    pub version: u32,
+    pub id: u64,
+    // node that sent the request, not set for responses
+    pub sender: Option<Neighbor>,
+    // virtual node the request is meant for, the first virtual node of
+    // the receiver handles it if not set or unknown
+    #[serde(default)]
+    pub receiver: Option<Identifier>,
+    // the encoded message is kept as bytes so that the envelope
+    // can still be read if the message is not understood
+    pub message: Option<ByteBuf>,
+    // hmac of all other fields if the cluster uses a key
+    #[serde(default)]
+    pub mac: Option<ByteBuf>,
+}
+
+impl Envelope {
+    pub fn new(
+        version: u32,
+        id: u64,
+        sender: Option<Neighbor>,
+        message: Option<&Message>,
+    ) -> Result<Self, MessageError> {
+        let message = match message {
+            Some(msg) => Some(ByteBuf::from(serde_cbor::to_vec(msg)?)),
+            None => None,
+        };
+        Ok(Envelope {
+            version,
+            id,
+            sender,
+            receiver: None,
+            message,
+            mac: None,
+        })
+    }
+
+    // the nonces bind the mac to the connection and direction it is sent in
+    fn signed_data(&self, nonces: &[u8]) -> Vec<u8> {
+        let fields = (
+            ByteBuf::from(nonces),
+            self.version,
+            self.id,
+            &self.sender,
+            &self.receiver,
+            &self.message,
+        );
+        serde_cbor::to_vec(&fields).unwrap_or_default()
+    }
+
+    pub fn sign(&mut self, key: &ClusterKey, nonces: &[u8]) {
+        self.mac = Some(ByteBuf::from(key.sign(&self.signed_data(nonces))));
+    }
+
+    pub fn is_signed_by(&self, key: &ClusterKey, nonces: &[u8]) -> bool {
+        match &self.mac {
+            Some(mac) => key.verify(&self.signed_data(nonces), mac),
+            None => false,
+        }
+    }
+
+    pub fn message(&self) -> Result<Option<Message>, serde_cbor::Error> {
+        match &self.message {
+            Some(buf) => Ok(Some(serde_cbor::from_slice(buf)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+// only used to find out which request a broken envelope belongs to
+#[derive(Deserialize)]
+struct EnvelopeId {
+    id: u64,
+}
+
+// reads the body of the next frame, returns None if the connection was closed
+pub async fn read_frame<R>(reader: &mut R) -> Result<Option<Vec<u8>>, MessageError>
+where
+    R: AsyncRead + Unpin,
+{
+    read_frame_limited(reader, MAX_FRAME_LEN).await
+}
+
+// reads the next frame if it is not larger than the given length
+// the buffer only grows as the body arrives, so a peer can not make
+// us allocate the declared length without sending it
+async fn read_frame_limited<R>(
+    reader: &mut R,
+    max_len: u32,
+) -> Result<Option<Vec<u8>>, MessageError>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = match reader.read_u32().await {
+        Ok(len) => len,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if len > max_len {
+        return Err(MessageError::InvalidData(format!(
+            "frame of {:} bytes is too large",
+            len
+        )));
+    }
+    let mut buf = Vec::new();
+    reader.take(len as u64).read_to_end(&mut buf).await?;
+    if buf.len() < len as usize {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(Some(buf))
+}
+
+// decodes an envelope, on failure the id of the envelope is returned
+// if it can still be read (0 otherwise) so that the request can be answered
+pub fn decode_envelope(buf: &[u8]) -> Result<Envelope, (u64, serde_cbor::Error)> {
+    serde_cbor::from_slice(buf).map_err(|err| {
+        let id = serde_cbor::from_slice::<EnvelopeId>(buf)
+            .map(|e| e.id)
+            .unwrap_or(0);
+        (id, err)
+    })
+}
+
+pub async fn write_frame<W, T>(writer: &mut W, frame: &T) -> Result<(), MessageError>
+where
+    W: AsyncWrite + Unpin,
+    T: Serialize,
+{
+    let body = serde_cbor::to_vec(frame)?;
+    let mut buf = Vec::with_capacity(body.len() + 4);
+    buf.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    buf.extend_from_slice(&body);
+    writer.write_all(&buf).await?;
+    Ok(())
+}
+
+// the halves of a plain or tls connection
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+fn split<S>(stream: S) -> (Reader, Writer)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    (Box::new(reader), Box::new(writer))
+}
+
+// a long-lived connection to another node that is shared by all requests
+struct Connection {
+    // protocol version agreed on in the handshake
+    version: u32,
+    sender: Option<Neighbor>,
+    // signs requests and checks the responses if set
+    key: Option<ClusterKey>,
+    nonces: Nonces,
+    writer: Mutex<Writer>,
+    // requests that wait for their response, by request id
+    pending: std::sync::Mutex<HashMap<u64, oneshot::Sender<Option<Message>>>>,
+    next_id: AtomicU64,
+    closed: AtomicBool,
+}
+
+impl Connection {
+    async fn open(
+        addr: SocketAddr,
+        sender: Option<Neighbor>,
+        config: &NetworkConfig,
+        tls: Option<&TlsConfig>,
+        key: Option<ClusterKey>,
+    ) -> Result<Arc<Self>, MessageError> {
+        let connect = async {
+            let stream = TcpStream::connect(addr).await?;
+            stream.set_nodelay(true)?;
+            Ok::<_, io::Error>(match tls {
+                Some(tls) => split(tls.connect(addr, stream).await?),
+                None => split(stream),
+            })
+        };
+        let (mut reader, mut writer) = timeout(config.connect_timeout, connect)
+            .await
+            .map_err(|_| MessageError::Timeout(addr))??;
+
+        let handshake = async {
+            let hello = Handshake::Hello(SUPPORTED_VERSIONS.to_vec());
+            write_frame(&mut writer, &hello).await?;
+            let version = match read_handshake(&mut reader).await? {
+                Handshake::Accept(version) if SUPPORTED_VERSIONS.contains(&version) => version,
+                Handshake::Reject(versions) => {
+                    return Err(MessageError::IncompatibleVersion(addr, versions))
+                }
+                other => return Err(unexpected_handshake(other)),
+            };
+            // all supported versions compare the identifier spaces and
+            // exchange nonces
+            let own = auth::new_nonce()?;
+            write_frame(&mut writer, &Handshake::Space(config.id_space)).await?;
+            write_frame(&mut writer, &Handshake::Nonce(ByteBuf::from(own.clone()))).await?;
+            match read_handshake(&mut reader).await? {
+                Handshake::Space(space) if space == config.id_space => {}
+                Handshake::Space(space) => {
+                    return Err(MessageError::IncompatibleIdSpace(addr, space))
+                }
+                other => return Err(unexpected_handshake(other)),
+            }
+            let peer = read_nonce(&mut reader).await?;
+            Ok((version, Nonces { own, peer }))
+        };
+        let (version, nonces) = timeout(config.read_timeout, handshake)
+            .await
+            .map_err(|_| MessageError::Timeout(addr))??;
+
+        let connection = Arc::new(Connection {
+            version,
+            sender,
+            key,
+            nonces,
+            writer: Mutex::new(writer),
+            pending: std::sync::Mutex::new(HashMap::new()),
+            // id 0 is used for errors that belong to no known request
+            next_id: AtomicU64::new(1),
+            closed: AtomicBool::new(false),
+        });
+        tokio::spawn(connection.clone().receive(reader));
+        Ok(connection)
+    }
+
+    // hands the responses to the waiting requests until the connection closes
+    async fn receive(self: Arc<Self>, mut reader: Reader) {
+        while let Ok(Some(buf)) = read_frame(&mut reader).await {
+            let (id, message) = match decode_envelope(&buf) {
+                Ok(envelope) if !self.is_authentic(&envelope) => (
+                    envelope.id,
+                    Some(Message::Error(RemoteError::Unauthenticated)),
+                ),
+                Ok(envelope) => match envelope.message() {
+                    Ok(message) => (envelope.id, message),
+                    Err(err) => (envelope.id, Some(malformed(err))),
+                },
+                Err((id, err)) => (id, Some(malformed(err))),
+            };
+            if let Some(tx) = self.pending.lock().unwrap().remove(&id) {
+                let _ = tx.send(message);
+            }
+        }
+        self.closed.store(true, Ordering::SeqCst);
+        // dropping the senders fails all requests that still wait
+        self.pending.lock().unwrap().clear();
+    }
+
+    fn is_authentic(&self, envelope: &Envelope) -> bool {
+        match &self.key {
+            Some(key) => envelope.is_signed_by(key, &self.nonces.received()),
+            None => true,
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    async fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = self.writer.lock().await.shutdown().await;
+    }
+
+    async fn request(
+        &self,
+        msg: &Message,
+        receiver: Option<Identifier>,
+        addr: SocketAddr,
+        config: &NetworkConfig,
+    ) -> Result<Option<Message>, MessageError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut envelope = Envelope::new(self.version, id, self.sender, Some(msg))?;
+        envelope.receiver = receiver;
+        if let Some(key) = &self.key {
+            envelope.sign(key, &self.nonces.sent());
+        }
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        let written = timeout(config.write_timeout, async {
+            write_frame(&mut *self.writer.lock().await, &envelope).await
+        })
+        .await;
+        if let Err(err) = written
+            .map_err(|_| MessageError::Timeout(addr))
+            .and_then(|r| r)
+        {
+            // the frame might be written partially, so the connection can not be used anymore
+            self.pending.lock().unwrap().remove(&id);
+            self.close().await;
+            return Err(err);
+        }
+
+        match timeout(config.read_timeout, rx).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(_)) => Err(io::Error::from(io::ErrorKind::ConnectionReset).into()),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(MessageError::Timeout(addr))
+            }
+        }
+    }
+}
+
+fn malformed(err: serde_cbor::Error) -> Message {
+    Message::Error(RemoteError::Malformed(err.to_string()))
+}
+
+// the connection to an address, locked while it is (re)opened
+type ConnectionSlot = Arc<Mutex<Option<Arc<Connection>>>>;
+
+// connections to other nodes, at most one per address
+pub struct ConnectionPool {
+    config: NetworkConfig,
+    // node that the requests are sent from
+    sender: Option<Neighbor>,
+    // connections are plaintext without tls config
+    tls: Option<TlsConfig>,
+    // frames are not signed without cluster key
+    key: Option<ClusterKey>,
+    connections: std::sync::Mutex<HashMap<SocketAddr, ConnectionSlot>>,
+}
+
+impl ConnectionPool {
+    pub fn new(
+        config: NetworkConfig,
+        sender: Option<Neighbor>,
+        tls: Option<TlsConfig>,
+        key: Option<ClusterKey>,
+    ) -> Self {
+        ConnectionPool {
+            config,
+            sender,
+            tls,
+            key,
+            connections: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    // returns the open connection to the address or opens a new one
+    async fn connection(&self, addr: SocketAddr) -> Result<Arc<Connection>, MessageError> {
+        let slot = self
+            .connections
+            .lock()
+            .unwrap()
+            .entry(addr)
+            .or_default()
+            .clone();
+        let mut slot = slot.lock().await;
+        if let Some(connection) = slot.as_ref() {
+            if !connection.is_closed() {
+                return Ok(connection.clone());
+            }
+        }
+        let connection = Connection::open(
+            addr,
+            self.sender,
+            &self.config,
+            self.tls.as_ref(),
+            self.key.clone(),
+        )
+        .await?;
+        *slot = Some(connection.clone());
+        Ok(connection)
+    }
+
+    // sends a message and waits for the response
+    // idempotent messages are retried with exponential backoff
+    pub async fn send_message(
+        &self,
+        msg: Message,
+        addr: SocketAddr,
+        receiver: Option<Identifier>,
+    ) -> Result<Option<Message>, MessageError> {
+        let attempts = if msg.is_idempotent() {
+            self.config.retries + 1
+        } else {
+            1
+        };
+        let mut backoff = self.config.retry_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.send_message_once(&msg, addr, receiver).await {
+                Err(err) if attempt < attempts && err.is_transient() => {
+                    sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send_message_once(
+        &self,
+        msg: &Message,
+        addr: SocketAddr,
+        receiver: Option<Identifier>,
+    ) -> Result<Option<Message>, MessageError> {
+        let connection = self.connection(addr).await?;
+        connection.request(msg, receiver, addr, &self.config).await
+    }
+}
+
+// agrees on a protocol version with the client of a new connection
+// and exchanges nonces with it
+async fn accept_handshake(
+    peer: SocketAddr,
+    space: IdSpace,
+    reader: &mut Reader,
+    writer: &mut Writer,
+) -> Result<(u32, Nonces), MessageError> {
+    let versions = match read_handshake(reader).await? {
+        Handshake::Hello(versions) => versions,
+        other => return Err(unexpected_handshake(other)),
+    };
+    let version = match negotiate_version(&versions) {
+        Some(version) => version,
+        None => {
+            write_frame(writer, &Handshake::Reject(SUPPORTED_VERSIONS.to_vec())).await?;
+            return Err(MessageError::IncompatibleVersion(peer, versions));
+        }
+    };
+    write_frame(writer, &Handshake::Accept(version)).await?;
+
+    // all supported versions compare the identifier spaces and
+    // exchange nonces
+    let peer_space = match read_handshake(reader).await? {
+        Handshake::Space(peer_space) => peer_space,
+        other => return Err(unexpected_handshake(other)),
+    };
+    let peer_nonce = read_nonce(reader).await?;
+    write_frame(writer, &Handshake::Space(space)).await?;
+    if peer_space != space {
+        return Err(MessageError::IncompatibleIdSpace(peer, peer_space));
+    }
+    let own = auth::new_nonce()?;
+    write_frame(writer, &Handshake::Nonce(ByteBuf::from(own.clone()))).await?;
+    let nonces = Nonces {
+        own,
+        peer: peer_nonce,
+    };
+    Ok((version, nonces))
+}
+
+// answers all requests that arrive on the connection, each one in its own task
+// with tls the peer has to authenticate with a certificate signed by the ca,
+// with a cluster key every request has to be signed with it and the nonces
+// of the connection
+pub async fn serve_connection<F, Fut>(
+    stream: TcpStream,
+    tls: Option<TlsConfig>,
+    key: Option<ClusterKey>,
+    config: NetworkConfig,
+    handler: F,
+) where
+    F: Fn(Message, Option<Identifier>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Option<Message>> + Send + 'static,
+{
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(_) => return,
+    };
+    let _ = stream.set_nodelay(true);
+    let (mut reader, mut writer) = match tls {
+        // the tls handshake gets as much time as the chord handshake after it
+        Some(tls) => match timeout(config.read_timeout, tls.acceptor.accept(stream)).await {
+            Ok(Ok(stream)) => split(stream),
+            Ok(Err(err)) => {
+                warn!(%peer, error = ?err, "tls handshake failed");
+                return;
+            }
+            Err(_) => {
+                warn!(%peer, "tls handshake timed out");
+                return;
+            }
+        },
+        None => split(stream),
+    };
+    // a client that never says hello would keep the connection open forever
+    let handshake = accept_handshake(peer, config.id_space, &mut reader, &mut writer);
+    let (version, nonces) = match timeout(config.read_timeout, handshake)
+        .await
+        .map_err(|_| MessageError::Timeout(peer))
+        .and_then(|r| r)
+    {
+        Ok(handshake) => handshake,
+        Err(err) => {
+            warn!(%peer, error = ?err, "rejected connection");
+            return;
+        }
+    };
+
+    let writer = Arc::new(Responder {
+        writer: Mutex::new(writer),
+        key,
+        nonces,
+    });
     loop {
-        let (mut tcp_stream, _) = listener.accept().await?;
+        let buf = match read_frame(&mut reader).await {
+            Ok(Some(buf)) => buf,
+            Ok(None) => break,
+            Err(err) => {
+                // the connection can not be read any further
+                warn!(%peer, error = ?err, "error reading frame");
+                let error = Message::Error(RemoteError::Malformed(format!("{:?}", err)));
+                let _ = writer.send(version, 0, Some(error)).await;
+                break;
+            }
+        };
+        let (id, request) = match decode_envelope(&buf) {
+            Ok(envelope) if !writer.is_authentic(&envelope) => {
+                (envelope.id, Err(RemoteError::Unauthenticated))
+            }
+            Ok(envelope) if envelope.version != version => {
+                let err = RemoteError::UnsupportedVersion(envelope.version, version);
+                (envelope.id, Err(err))
+            }
+            Ok(envelope) => match envelope.message() {
+                Ok(msg) => (envelope.id, Ok(msg.map(|msg| (msg, envelope.receiver)))),
+                Err(err) => (envelope.id, Err(RemoteError::Malformed(err.to_string()))),
+            },
+            Err((id, err)) => (id, Err(RemoteError::Malformed(err.to_string()))),
+        };
+        let request = match request {
+            Ok(request) => request,
+            Err(err) => {
+                debug!(%peer, message_id = id, error = ?err, "rejected message");
+                let error = Some(Message::Error(err));
+                if let Err(err) = writer.send(version, id, error).await {
+                    warn!(%peer, message_id = id, error = ?err, "error sending response");
+                    break;
+                }
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        let writer = writer.clone();
+        // everything logged while handling the request carries its id
+        let span = info_span!("request", %peer, message_id = id);
+        tokio::spawn(
+            async move {
+                let message = match request {
+                    Some((msg, receiver)) => handler(msg, receiver).await,
+                    None => None,
+                };
+                if let Err(err) = writer.send(version, id, message).await {
+                    warn!(error = ?err, "error sending response");
+                }
+            }
+            .instrument(span),
+        );
+    }
+}
 
-        let mut send_buf = Vec::with_capacity(32);
-        tcp_stream.read_to_end(&mut send_buf).await.unwrap();
-        let msg: Message = serde_cbor::from_slice(send_buf.as_slice()).unwrap();
+// sends the responses of a served connection
+struct Responder {
+    writer: Mutex<Writer>,
+    key: Option<ClusterKey>,
+    nonces: Nonces,
+}
+
+impl Responder {
+    fn is_authentic(&self, envelope: &Envelope) -> bool {
+        match &self.key {
+            Some(key) => envelope.is_signed_by(key, &self.nonces.received()),
+            None => true,
+        }
+    }
 
-        if let Some(resp) = handler(msg).await {
-            let buf = serde_cbor::to_vec(&resp).unwrap();
-            tcp_stream.write_all(&buf).await.unwrap();
-            tcp_stream.shutdown().await.unwrap();
+    async fn send(
+        &self,
+        version: u32,
+        id: u64,
+        message: Option<Message>,
+    ) -> Result<(), MessageError> {
+        let mut envelope = Envelope::new(version, id, None, message.as_ref())?;
+        if let Some(key) = &self.key {
+            envelope.sign(key, &self.nonces.sent());
         }
+        write_frame(&mut *self.writer.lock().await, &envelope).await
+    }
+}
+
+// serves every connection accepted on the listener, a panic while serving
+// a connection or handling a request is logged and counted, a request whose
+// handler panicked is answered with an error
+pub async fn listen_for_messages<F, Fut>(
+    listener: TcpListener,
+    tls: Option<TlsConfig>,
+    key: Option<ClusterKey>,
+    config: NetworkConfig,
+    metrics: Arc<Metrics>,
+    handler: F,
+) where
+    F: Fn(Message, Option<Identifier>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Option<Message>> + Send + 'static,
+{
+    let handler_metrics = metrics.clone();
+    let handler = move |msg: Message, receiver: Option<Identifier>| {
+        let handled = AssertUnwindSafe(handler(msg, receiver)).catch_unwind();
+        let metrics = handler_metrics.clone();
+        async move {
+            handled.await.unwrap_or_else(|_| {
+                error!("request handler panicked");
+                metrics.panics.inc();
+                Some(Message::Error(RemoteError::Failed("panicked".to_string())))
+            })
+        }
+    };
+    loop {
+        let (tcp_stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(error = ?err, "error accepting connection");
+                continue;
+            }
+        };
+        let connection = tokio::spawn(serve_connection(
+            tcp_stream,
+            tls.clone(),
+            key.clone(),
+            config,
+            handler.clone(),
+        ));
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                if err.is_panic() {
+                    error!(%peer, "connection task panicked");
+                    metrics.panics.inc();
+                }
+            }
+        });
     }
 }
 
@@ -93,31 +946,33 @@ where
 macro_rules! handle_message {
     // return error if message is not handled by given pattern matching
     // e.g.
-    // handle_message!(self.addr, msg, {
+    // handle_message!(net, neighbor, msg, {
     //    Message::LookupResult(addr) => Neighbor::new(addr)
     // })
     // returns an error if response is not of type Message::LookupResult
-    ($addr:expr , $msg: expr,{ $($p:pat => $handle:expr)+}) => {{
-        let response = network::send_message($msg, $addr).await?;
+    ($net:expr , $to:expr , $msg: expr,{ $($p:pat => $handle:expr)+}) => {{
+        let msg = $msg;
+        let response = $net.send(msg.clone(), $to.addr, Some($to.id)).await?;
         match response {
             Some(resp) => match resp {
                 $(
                     $p => Ok($handle),
                 )+
-                r => Err(network::MessageError::UnexpectedResponse($msg, Some(r))),
+                network::Message::Error(err) => Err(network::MessageError::Remote(err)),
+                r => Err(network::MessageError::UnexpectedResponse(Box::new(msg), Some(Box::new(r)))),
             },
-            None => Err(network::MessageError::UnexpectedResponse($msg, None)),
+            None => Err(network::MessageError::UnexpectedResponse(Box::new(msg), None)),
         }
     }};
 
     // no answer expected, return error if answer is not None
-    ($addr:expr , $msg: expr) => {{
+    ($net:expr , $to:expr , $msg: expr) => {{
         let msg = $msg;
-        let response = network::send_message(msg, $addr).await?;
-        if response.is_some(){
-            Err(network::MessageError::UnexpectedResponse(msg, response))
-        }else{
-            Ok(())
+        let response = $net.send(msg.clone(), $to.addr, Some($to.id)).await?;
+        match response {
+            None => Ok(()),
+            Some(network::Message::Error(err)) => Err(network::MessageError::Remote(err)),
+            Some(r) => Err(network::MessageError::UnexpectedResponse(Box::new(msg), Some(Box::new(r)))),
         }
     }};
 }
@@ -1,394 +1,1826 @@
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use serde_bytes::ByteBuf;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{self, Display};
 use std::hash::Hash;
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
-use warp::http;
-use warp::hyper::{Client, Uri};
+use tokio::time::{sleep, timeout, Instant};
+use tracing::{debug, info, instrument, warn, Span};
 
+use crate::auth::ClusterKey;
+use crate::events::{Event, Events};
 use crate::handle_message;
+use crate::metrics::{MeteredTransport, Metrics};
+use crate::ring::{Ring, RingEntry, Stabilization};
+use crate::storage::{Record, Storage};
+use crate::tls::TlsConfig;
+use crate::transport::{TcpTransport, Transport};
 use crate::{
-    network::{self, Message, MessageError},
-    routing::id::{HashIdentifier, Identifier},
+    network::{self, Entry, Message, MessageError, NetworkConfig, RemoteError},
+    routing::id::{HashIdentifier, IdSpace, Identifier},
 };
 
+// upper bound of the nodes a walk of the ring visits
+const MAX_RING_NODES: usize = 4096;
+
 #[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub struct Neighbor {
-    id: Identifier,
+    pub id: Identifier,
     pub addr: SocketAddr,
     pub web_addr: SocketAddr,
 }
 
 impl Neighbor {
-    fn new(addr: SocketAddr, web_addr: SocketAddr) -> Self {
+    fn new(addr: SocketAddr, web_addr: SocketAddr, space: &IdSpace) -> Self {
         Neighbor {
-            id: addr.hash_id(),
-            addr: addr,
-            web_addr: web_addr,
+            id: addr.hash_id(space),
+            addr,
+            web_addr,
         }
     }
 
-    async fn find_successor(&self, id: Identifier) -> Result<Neighbor, MessageError> {
-        let msg = Message::Lookup(id);
-        handle_message!(self.addr, msg, {
-            Message::LookupResult(neighbor) => neighbor
+    // returns the successor of the id, the number of nodes the lookup was
+    // forwarded to after this one and, if trace is set, those nodes
+    async fn find_successor(
+        &self,
+        net: &dyn Transport,
+        id: Identifier,
+        trace: bool,
+    ) -> Result<(Neighbor, u32, Vec<TraceHop>), MessageError> {
+        let msg = Message::Lookup(id, trace);
+        handle_message!(net, self, msg, {
+            Message::LookupResult(neighbor, count, hops) => {
+                (neighbor, count, hops.unwrap_or_default())
+            }
         })
     }
 
-    async fn get_predecessor(&self) -> Result<Option<Neighbor>, MessageError> {
+    // asks the node for the next hop of an iterative lookup
+    async fn closest_preceding(
+        &self,
+        net: &dyn Transport,
+        id: Identifier,
+    ) -> Result<Hop, MessageError> {
+        let msg = Message::ClosestPreceding(id);
+        handle_message!(net, self, msg, {
+            Message::ClosestPrecedingResult(hop) => hop
+        })
+    }
+
+    async fn get_predecessor(&self, net: &dyn Transport) -> Result<Option<Neighbor>, MessageError> {
         let msg = Message::GetPredecessor;
-        handle_message!(self.addr, msg, {
+        handle_message!(net, self, msg, {
             Message::PredecessorResponse(neighbor) => neighbor
         })
     }
 
-    async fn get_succcessor(&self) -> Result<Neighbor, MessageError> {
-        let msg = Message::GetSuccessor;
-        handle_message!(self.addr, msg, {
-            Message::SuccessorResponse(neighbor) => neighbor
+    // hands over key-value pairs to the node, returns the keys it stored
+    async fn push_keys(
+        &self,
+        net: &dyn Transport,
+        entries: Vec<Entry>,
+    ) -> Result<Vec<String>, MessageError> {
+        let msg = Message::PushKeys(entries);
+        handle_message!(net, self, msg, {
+            Message::KeysAck(keys) => keys
+        })
+    }
+
+    // stores copies of the given key-value pairs on the node
+    async fn replicate(
+        &self,
+        net: &dyn Transport,
+        entries: Vec<Entry>,
+    ) -> Result<Vec<String>, MessageError> {
+        let msg = Message::Replicate(entries);
+        handle_message!(net, self, msg, {
+            Message::KeysAck(keys) => keys
+        })
+    }
+
+    // deletes the copies of the given keys the node stores
+    async fn drop_replicas(
+        &self,
+        net: &dyn Transport,
+        keys: Vec<String>,
+    ) -> Result<(), MessageError> {
+        handle_message!(net, self, Message::DropReplicas(keys))
+    }
+
+    async fn get_successor_list(&self, net: &dyn Transport) -> Result<Vec<Neighbor>, MessageError> {
+        let msg = Message::GetSuccessorList;
+        handle_message!(net, self, msg, {
+            Message::SuccessorListResponse(list) => list
         })
     }
 
-    async fn notify(&self, neighbor: Neighbor) -> Result<(), MessageError> {
-        handle_message!(self.addr, Message::Notify(neighbor))
+    async fn ping(&self, net: &dyn Transport) -> Result<(), MessageError> {
+        handle_message!(net, self, Message::Ping, {
+            Message::Pong => ()
+        })
+    }
+
+    async fn notify(&self, net: &dyn Transport, neighbor: Neighbor) -> Result<(), MessageError> {
+        handle_message!(net, self, Message::Notify(neighbor))
     }
 
     // tell a node that its predecessor left the network
     // and the given node is his new predecessor
     async fn leave_predecessor(
         &self,
+        net: &dyn Transport,
         new_predecessor: Option<Neighbor>,
     ) -> Result<(), MessageError> {
-        handle_message!(self.addr, Message::LeavePredecessor(new_predecessor))
+        handle_message!(net, self, Message::LeavePredecessor(new_predecessor))
     }
 
     // tell a node that its successor left the network
     // and the given node is his new successor
-    async fn leave_successor(&self, new_successor: Neighbor) -> Result<(), MessageError> {
-        handle_message!(self.addr, Message::LeaveSuccessor(new_successor))
+    async fn leave_successor(
+        &self,
+        net: &dyn Transport,
+        new_successor: Neighbor,
+    ) -> Result<(), MessageError> {
+        handle_message!(net, self, Message::LeaveSuccessor(new_successor))
+    }
+
+    // reads the value of a key the node is responsible for
+    async fn get(&self, net: &dyn Transport, key: String) -> Result<Option<ByteBuf>, MessageError> {
+        let result = handle_message!(net, self, Message::Get(key), {
+            Message::GetResult(result) => result
+        })?;
+        result.map_err(MessageError::Remote)
+    }
+
+    // stores a value for a key the node is responsible for
+    async fn put(
+        &self,
+        net: &dyn Transport,
+        key: String,
+        value: ByteBuf,
+    ) -> Result<(), MessageError> {
+        let result = handle_message!(net, self, Message::Put(key, value), {
+            Message::PutAck(result) => result
+        })?;
+        result.map_err(MessageError::Remote)
+    }
+
+    // deletes a key the node is responsible for, returns whether it existed
+    async fn delete(&self, net: &dyn Transport, key: String) -> Result<bool, MessageError> {
+        let result = handle_message!(net, self, Message::Delete(key), {
+            Message::DeleteAck(result) => result
+        })?;
+        result.map_err(MessageError::Remote)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NodeConfig {
+    // number of successors a node keeps track of (including its direct successor)
+    pub successor_list_length: usize,
+    // number of nodes that store a copy of each key (including its owner)
+    pub replication_factor: usize,
+    // time the predecessor has to answer a ping before it is considered dead
+    pub ping_timeout: Duration,
+    // number of positions on the ring that the node takes
+    pub vnodes: usize,
+    // how the owner of a key is found if the request does not choose
+    pub lookup_mode: LookupMode,
+    // time each hop of an iterative lookup has to answer
+    pub hop_timeout: Duration,
+    pub network: NetworkConfig,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            successor_list_length: 3,
+            replication_factor: 1,
+            ping_timeout: Duration::from_millis(500),
+            vnodes: 1,
+            lookup_mode: LookupMode::Recursive,
+            hop_timeout: Duration::from_secs(1),
+            network: NetworkConfig::default(),
+        }
+    }
+}
+
+// recursive lookups are forwarded from node to node, each one waiting for
+// the answer of the next, iterative lookups are driven by the node that
+// looks up the key, which asks one hop after another for the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LookupMode {
+    Recursive,
+    Iterative,
+}
+
+impl FromStr for LookupMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "recursive" => Ok(LookupMode::Recursive),
+            "iterative" => Ok(LookupMode::Iterative),
+            _ => Err(format!("unknown lookup mode {:}", s)),
+        }
+    }
+}
+
+// answer of a hop of an iterative lookup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Hop {
+    // the successor of the identifier
+    Owner(Neighbor),
+    // nodes closer to the identifier, the closest preceding finger
+    // followed by the successors of the hop
+    Next(Vec<Neighbor>),
+}
+
+// a node that a lookup visited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceHop {
+    pub node: Neighbor,
+    // time until the node answered, measured by the node that asked it
+    // for recursive lookups this includes all following hops
+    pub latency: Duration,
+    // the node failed and the lookup continued without it
+    pub error: Option<String>,
+}
+
+// path of a lookup that was traced, starting at the virtual node
+// of the node that looked up the identifier
+#[derive(Debug, Clone)]
+pub struct LookupTrace {
+    pub hops: Vec<TraceHop>,
+    pub owner: Result<Neighbor, String>,
+}
+
+// the nodes a lookup visited, they are only counted unless it is traced
+#[derive(Default)]
+struct LookupPath {
+    count: u32,
+    hops: Option<Vec<TraceHop>>,
+}
+
+impl LookupPath {
+    fn traced(hops: Vec<TraceHop>) -> Self {
+        LookupPath {
+            count: 0,
+            hops: Some(hops),
+        }
+    }
+
+    fn is_traced(&self) -> bool {
+        self.hops.is_some()
+    }
+
+    fn record(&mut self, node: Neighbor, latency: Duration, error: Option<&MessageError>) {
+        self.count += 1;
+        if let Some(hops) = &mut self.hops {
+            hops.push(TraceHop {
+                node,
+                latency,
+                error: error.map(|err| format!("{:?}", err)),
+            });
+        }
+    }
+
+    // adds the hops a node reported after it was asked
+    fn extend(&mut self, count: u32, later_hops: Vec<TraceHop>) {
+        self.count += count;
+        if let Some(hops) = &mut self.hops {
+            hops.extend(later_hops);
+        }
+    }
+}
+
+// identifier of the i-th virtual node of the node with the given address,
+// the first one has the identifier of the address itself
+pub fn vnode_id(addr: SocketAddr, i: usize, space: &IdSpace) -> Identifier {
+    if i == 0 {
+        addr.hash_id(space)
+    } else {
+        format!("{:}#{:}", addr, i).hash_id(space)
+    }
+}
+
+// a position of a node on the ring with its own neighbors,
+// all virtual nodes of a node share its storage
+pub struct VirtualNode {
+    pub id: Identifier,
+    pub predecessor: Mutex<Option<Neighbor>>,
+    // last predecessor that did not answer a ping and how often that happened
+    pub failed_predecessor: Mutex<Option<Neighbor>>,
+    pub predecessor_failures: Mutex<u64>,
+    pub successor: Mutex<Neighbor>,
+    // successors that follow the direct successor, used when it fails
+    pub successor_list: Mutex<Vec<Neighbor>>,
+    // the i-th finger is the first node that succeeds id + 2^i,
+    // there is one finger per bit of the identifier
+    pub fingers: Mutex<Vec<Option<Neighbor>>>,
+    // index of the finger that is fixed in the next fix_fingers run
+    next_finger: Mutex<usize>,
+    // successors that received the last full replication of the keys
+    // of this virtual node
+    replica_holders: Mutex<Vec<Neighbor>>,
+}
+
+impl VirtualNode {
+    fn new(id: Identifier, predecessor: Option<Neighbor>, successor: Neighbor) -> Self {
+        VirtualNode {
+            id,
+            predecessor: Mutex::new(predecessor),
+            failed_predecessor: Mutex::new(None),
+            predecessor_failures: Mutex::new(0),
+            successor: Mutex::new(successor),
+            successor_list: Mutex::new(Vec::new()),
+            fingers: Mutex::new(vec![None; id.bits() as usize]),
+            next_finger: Mutex::new(0),
+            replica_holders: Mutex::new(Vec::new()),
+        }
+    }
+
+    // whether the id is in the range (predecessor, id] this virtual node is
+    // responsible for, which is the whole ring if the predecessor is unknown
+    pub async fn contains_id(&self, id: Identifier) -> bool {
+        self.predecessor
+            .lock()
+            .await
+            .map(|n| id.is_between(n.id, self.id))
+            .unwrap_or(true)
+    }
+
+    // returns the direct successor followed by the successor list
+    pub async fn successors(&self) -> Vec<Neighbor> {
+        let mut successors = vec![*self.successor.lock().await];
+        successors.extend(self.successor_list.lock().await.iter());
+        successors
     }
 }
 
-#[derive(Debug)]
 pub struct Node<Key, Value>
 where
-    Key: Eq + Hash + HashIdentifier<Identifier> + ToString,
-    Value: Clone + FromStr + ToString,
-    <Value as FromStr>::Err: fmt::Debug,
+    Key: Eq + Hash + HashIdentifier + FromStr + ToString + Send + 'static,
+    Value: Clone + Serialize + DeserializeOwned + Send + 'static,
+    <Key as FromStr>::Err: fmt::Debug,
 {
     pub address: SocketAddr,
     pub web_address: SocketAddr,
-    pub predecessor: Mutex<Option<Neighbor>>,
-    pub successor: Mutex<Neighbor>,
-    pub second_successor: Mutex<Option<Neighbor>>,
+    // the positions of the node on the ring, the first one has the id of the node
+    pub vnodes: Vec<VirtualNode>,
     pub sim_crash_state: Mutex<bool>,
 
     pub id: Identifier,
-    store: Mutex<HashMap<Key, Value>>,
+    pub config: NodeConfig,
+    pub metrics: Arc<Metrics>,
+    // changes of the ring seen by this node
+    pub events: Events,
+    transport: Arc<dyn Transport>,
+    store: Mutex<Box<dyn Storage<Key, Record<Value>>>>,
+    // copies of keys owned by one of our predecessors
+    replicas: Mutex<Box<dyn Storage<Key, Record<Value>>>>,
+    // held while keys are handed off to their owners
+    handing_off: Mutex<()>,
 }
 
 impl<Key, Value> Node<Key, Value>
 where
-    Key: Eq + Hash + HashIdentifier<Identifier> + ToString,
-    Value: Clone + FromStr + ToString,
-    <Value as FromStr>::Err: fmt::Debug,
+    Key: Eq + Hash + HashIdentifier + FromStr + ToString + Send + 'static,
+    Value: Clone + Serialize + DeserializeOwned + Send + 'static,
+    <Key as FromStr>::Err: fmt::Debug,
 {
-    pub fn new(addr: SocketAddr, web_addr: SocketAddr) -> Self {
+    pub fn new(addr: SocketAddr, web_addr: SocketAddr, config: NodeConfig) -> Self {
+        Node::with_storage(
+            addr,
+            web_addr,
+            config,
+            Box::new(HashMap::<Key, Record<Value>>::new()),
+            Box::new(HashMap::<Key, Record<Value>>::new()),
+            None,
+            None,
+        )
+    }
+
+    // creates a node that uses the given storage for its keys and replicas
+    // and talks to other nodes over tls and with signed frames if a tls config
+    // or cluster key is given
+    pub fn with_storage(
+        addr: SocketAddr,
+        web_addr: SocketAddr,
+        config: NodeConfig,
+        store: Box<dyn Storage<Key, Record<Value>>>,
+        replicas: Box<dyn Storage<Key, Record<Value>>>,
+        tls: Option<TlsConfig>,
+        key: Option<ClusterKey>,
+    ) -> Self {
+        let sender = Some(Neighbor::new(addr, web_addr, &config.network.id_space));
+        let transport = Arc::new(TcpTransport::with_security(
+            config.network,
+            sender,
+            tls,
+            key,
+        ));
+        Node::with_transport(addr, web_addr, config, transport, store, replicas)
+    }
+
+    // creates a node that sends its messages with the given transport
+    // instead of tcp, only the identifier space of config.network is used then
+    pub fn with_transport(
+        addr: SocketAddr,
+        web_addr: SocketAddr,
+        config: NodeConfig,
+        transport: Arc<dyn Transport>,
+        store: Box<dyn Storage<Key, Record<Value>>>,
+        replicas: Box<dyn Storage<Key, Record<Value>>>,
+    ) -> Self {
+        assert!(config.vnodes >= 1, "a node needs at least 1 virtual node");
+        let ids: Vec<Identifier> = (0..config.vnodes)
+            .map(|i| vnode_id(addr, i, &config.network.id_space))
+            .collect();
+        let vnodes = ids
+            .iter()
+            .map(|id| {
+                let (predecessor, successor) = Self::local_ring(addr, web_addr, &ids, *id);
+                VirtualNode::new(*id, predecessor, successor)
+            })
+            .collect();
+        let metrics = Arc::new(Metrics::default());
         Node {
             address: addr,
             web_address: web_addr,
-            predecessor: Mutex::new(None),
-            successor: Mutex::new(Neighbor::new(addr, web_addr)),
-            second_successor: Mutex::new(None),
+            vnodes,
             sim_crash_state: Mutex::new(false),
 
-            id: addr.hash_id(),
-            store: Mutex::new(HashMap::<Key, Value>::new()),
+            id: addr.hash_id(&config.network.id_space),
+            config,
+            metrics: metrics.clone(),
+            events: Events::default(),
+            transport: Arc::new(MeteredTransport::new(transport, metrics)),
+            store: Mutex::new(store),
+            replicas: Mutex::new(replicas),
+            handing_off: Mutex::new(()),
+        }
+    }
+
+    // neighbors of a virtual node in the ring that only consists of the
+    // virtual nodes of this node
+    fn local_ring(
+        addr: SocketAddr,
+        web_addr: SocketAddr,
+        ids: &[Identifier],
+        id: Identifier,
+    ) -> (Option<Neighbor>, Neighbor) {
+        let neighbor = |id| Neighbor { id, addr, web_addr };
+        if ids.len() == 1 {
+            return (None, neighbor(id));
         }
+        let mut sorted = ids.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let pos = sorted.iter().position(|i| *i == id).unwrap();
+        let predecessor = sorted[(pos + sorted.len() - 1) % sorted.len()];
+        let successor = sorted[(pos + 1) % sorted.len()];
+        (Some(neighbor(predecessor)), neighbor(successor))
+    }
+
+    fn net(&self) -> &dyn Transport {
+        self.transport.as_ref()
+    }
+
+    fn key_id(&self, key: &Key) -> Identifier {
+        key.hash_id(&self.config.network.id_space)
+    }
+
+    // the virtual node as neighbor of other nodes
+    fn neighbor(&self, vnode: &VirtualNode) -> Neighbor {
+        Neighbor {
+            id: vnode.id,
+            addr: self.address,
+            web_addr: self.web_address,
+        }
+    }
+
+    // the virtual node a request is meant for, the first one if it is unknown
+    fn vnode(&self, id: Option<Identifier>) -> &VirtualNode {
+        id.and_then(|id| self.vnodes.iter().find(|v| v.id == id))
+            .unwrap_or(&self.vnodes[0])
+    }
+
+    // the virtual node that most closely precedes the id, lookups
+    // started there take the fewest hops
+    fn preceding_vnode(&self, id: Identifier) -> &VirtualNode {
+        self.vnodes
+            .iter()
+            .min_by(|a, b| (id - a.id).partial_cmp(&(id - b.id)).unwrap())
+            .unwrap()
+    }
+
+    // the virtual node that most closely follows the id, which was
+    // responsible for it before other nodes joined in between
+    fn following_vnode(&self, id: Identifier) -> &VirtualNode {
+        self.vnodes
+            .iter()
+            .min_by(|a, b| (a.id - id).partial_cmp(&(b.id - id)).unwrap())
+            .unwrap()
+    }
+
+    // the predecessors of all virtual nodes, in the order of the virtual nodes
+    async fn predecessors(&self) -> Vec<Option<Neighbor>> {
+        let mut predecessors = Vec::with_capacity(self.vnodes.len());
+        for vnode in self.vnodes.iter() {
+            predecessors.push(*vnode.predecessor.lock().await);
+        }
+        predecessors
+    }
+
+    // the virtual node responsible for the id given the predecessors of all
+    // virtual nodes, None if the id belongs to another node
+    fn range_owner(
+        &self,
+        predecessors: &[Option<Neighbor>],
+        id: Identifier,
+    ) -> Option<&VirtualNode> {
+        self.vnodes
+            .iter()
+            .zip(predecessors)
+            .find(|(v, p)| p.map(|p| id.is_between(p.id, v.id)).unwrap_or(true))
+            .map(|(v, _)| v)
     }
 
     async fn contains_id(&self, id: Identifier) -> bool {
-        self.predecessor
-            .lock()
-            .await
-            .map(|n| id.is_between(n.id, self.id))
-            .unwrap_or(true)
+        self.range_owner(&self.predecessors().await, id).is_some()
+    }
+
+    fn parse_key(k: &str) -> Result<Key, MessageError> {
+        Key::from_str(k).map_err(|e| MessageError::InvalidData(format!("{:?}", e)))
+    }
+
+    fn parse_keys(keys: &[String]) -> Result<Vec<Key>, MessageError> {
+        keys.iter().map(|k| Self::parse_key(k)).collect()
+    }
+
+    // values are sent between nodes in their binary cbor encoding
+    fn encode_value(v: &Value) -> Result<ByteBuf, MessageError> {
+        Ok(ByteBuf::from(serde_cbor::to_vec(v)?))
+    }
+
+    fn decode_value(v: &[u8]) -> Result<Value, MessageError> {
+        serde_cbor::from_slice(v).map_err(|e| MessageError::InvalidData(format!("{:?}", e)))
+    }
+
+    fn to_entry(key: &Key, record: &Record<Value>) -> Result<Entry, MessageError> {
+        Ok(Entry {
+            key: key.to_string(),
+            version: record.version,
+            value: record.value.as_ref().map(Self::encode_value).transpose()?,
+        })
+    }
+
+    fn from_entry(entry: Entry) -> Result<(Key, Record<Value>), MessageError> {
+        let value = match entry.value {
+            Some(v) => Some(Self::decode_value(&v)?),
+            None => None,
+        };
+        Ok((
+            Self::parse_key(&entry.key)?,
+            Record {
+                version: entry.version,
+                value,
+            },
+        ))
+    }
+
+    // stores the entries unless newer versions of their keys are stored
+    // already, all of them are written at once
+    fn merge_entries(
+        store: &mut Box<dyn Storage<Key, Record<Value>>>,
+        entries: Vec<Entry>,
+    ) -> Result<(), MessageError> {
+        let mut newer: HashMap<Key, Record<Value>> = HashMap::new();
+        for entry in entries {
+            let (key, record) = Self::from_entry(entry)?;
+            let current = newer.get(&key).cloned().or_else(|| store.get(&key));
+            if record.is_newer_than(current.as_ref()) {
+                newer.insert(key, record);
+            }
+        }
+        store.insert_all(newer.into_iter().collect())?;
+        Ok(())
+    }
+
+    // version for a new write of a key, versions are timestamps but always
+    // increase for a key even if the clock goes backwards
+    fn next_version(current: Option<&Record<Value>>) -> u64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        current.map(|r| now.max(r.version + 1)).unwrap_or(now)
+    }
+
+    // metrics of the node in the prometheus text format
+    pub async fn render_metrics(&self) -> String {
+        // tombstones of deleted keys are kept forever but not counted
+        let count = |storage: &dyn Storage<Key, Record<Value>>| {
+            storage.iter().filter(|(_, r)| r.value.is_some()).count() as f64
+        };
+        let keys = count(self.store.lock().await.as_ref());
+        let replicas = count(self.replicas.lock().await.as_ref());
+        self.metrics.render(&[
+            (
+                "accord_store_keys",
+                "keys with a value stored by the node",
+                keys,
+            ),
+            (
+                "accord_replica_keys",
+                "copies of keys of other nodes that have a value",
+                replicas,
+            ),
+        ])
     }
 
     // finds the value for a given key within the chord ring
     pub async fn lookup(&self, key: Key) -> Result<Option<Value>, MessageError> {
-        let id = key.hash_id();
+        self.lookup_with(key, self.config.lookup_mode).await
+    }
+
+    #[instrument(skip_all, fields(node = %self.address, key = %key.to_string()))]
+    pub async fn lookup_with(
+        &self,
+        key: Key,
+        mode: LookupMode,
+    ) -> Result<Option<Value>, MessageError> {
+        if let Some(owner) = self.remote_owner(&key, mode).await? {
+            let value = owner.get(self.net(), key.to_string()).await?;
+            return value.map(|v| Self::decode_value(&v)).transpose();
+        }
+        let value = self.store.lock().await.get(&key).and_then(|r| r.value);
+        Ok(value)
+    }
+
+    // returns the node responsible for the key unless it is this node
+    async fn remote_owner(
+        &self,
+        key: &Key,
+        mode: LookupMode,
+    ) -> Result<Option<Neighbor>, MessageError> {
+        let id = self.key_id(key);
         if self.contains_id(id).await {
-            let value = self.store.lock().await.get(&key).map(|n| n.clone());
-            return Ok(value);
+            return Ok(None);
+        }
+        let owner = self.find_owner(id, mode).await?;
+        // the ring might not be stable yet, never forward requests to ourselves
+        if owner.addr == self.address {
+            Ok(None)
         } else {
-            let succ = self.successor.lock().await.clone();
-            let addr = succ.find_successor(id).await?;
-            let client = Client::new();
-
-            let url: Uri = format!("http://{:}/storage/{:}", addr.web_addr, key.to_string())
-                .parse()
-                .unwrap();
-
-            let res = client.get(url).await.unwrap();
-            match res.status() {
-                http::StatusCode::NOT_FOUND => Ok(None),
-                http::StatusCode::OK => {
-                    let body = warp::hyper::body::to_bytes(res).await.unwrap();
-                    let body_str = String::from_utf8(body.to_vec()).unwrap();
-                    let v = Value::from_str(body_str.as_str()).unwrap();
-                    Ok(Some(v))
-                }
-                status => Err(MessageError::HTTPStatusError(status)),
+            Ok(Some(owner))
+        }
+    }
+
+    // answers a request of another node, failures are sent back as errors
+    pub async fn handle_request(
+        &self,
+        msg: Message,
+        receiver: Option<Identifier>,
+    ) -> Option<Message> {
+        match self.handle_message(msg.clone(), receiver).await {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(node = %self.address, request = ?msg, error = ?err, "error handling message");
+                Some(Message::Error(err.into()))
             }
         }
     }
 
-    pub async fn handle_message(&self, msg: Message) -> Result<Option<Message>, MessageError> {
-        let scs = self.sim_crash_state.lock().await.clone();
-        if scs == true {
-            Err(MessageError::IOError(
+    // handles a request for the virtual node with the given id
+    #[instrument(skip_all, fields(node = %self.address, vnode, request = msg.name(), key))]
+    pub async fn handle_message(
+        &self,
+        msg: Message,
+        receiver: Option<Identifier>,
+    ) -> Result<Option<Message>, MessageError> {
+        let scs = *self.sim_crash_state.lock().await;
+        if scs {
+            return Err(MessageError::IOError(
                 std::io::ErrorKind::ConnectionRefused.into(),
-            ))
-        } else {
-            match msg {
-                Message::Lookup(id) => {
-                    let responsible_node = self.find_successor(id).await?;
-                    Ok(Some(Message::LookupResult(responsible_node)))
-                }
-                Message::Notify(addr) => {
-                    self.notify(addr.into()).await;
-                    Ok(None)
-                }
-                Message::GetPredecessor => {
-                    let pred = self.predecessor.lock().await.clone();
-                    let response = Message::PredecessorResponse(pred);
-                    Ok(Some(response))
-                }
-                Message::GetSuccessor => {
-                    let succ = self.successor.lock().await.clone();
-                    let response = Message::SuccessorResponse(succ);
-                    Ok(Some(response))
-                }
-                Message::LeaveSuccessor(new_succecessor) => {
-                    // our successor left so we need to update it to the
-                    // new given one
-                    let mut successor = self.successor.lock().await;
-                    // if given successor = self.successor, take self
-                    *successor = if successor.id == new_succecessor.id {
-                        Neighbor::new(self.address, self.web_address)
-                    } else {
-                        new_succecessor
-                    };
-                    Ok(None)
+            ));
+        }
+        self.metrics.messages_received.inc(msg.name());
+        let vnode = self.vnode(receiver);
+        let span = Span::current();
+        span.record("vnode", format_args!("{:x}", vnode.id));
+        match &msg {
+            Message::Lookup(id, _) | Message::ClosestPreceding(id) => {
+                span.record("key", format_args!("{:x}", id));
+            }
+            Message::Get(k) | Message::Put(k, _) | Message::Delete(k) => {
+                span.record("key", k.as_str());
+            }
+            _ => {}
+        }
+        match msg {
+            Message::Lookup(id, trace) => {
+                let mut path = if trace {
+                    LookupPath::traced(Vec::new())
+                } else {
+                    LookupPath::default()
+                };
+                let responsible_node = self.find_successor_traced(vnode, id, &mut path).await?;
+                Ok(Some(Message::LookupResult(
+                    responsible_node,
+                    path.count,
+                    path.hops,
+                )))
+            }
+            Message::ClosestPreceding(id) => {
+                let hop = self.next_hop(vnode, id).await;
+                Ok(Some(Message::ClosestPrecedingResult(hop)))
+            }
+            Message::Notify(addr) => {
+                // the keys that now belong to the new predecessor are handed
+                // off by our next stabilize run, not while the notifier waits
+                self.notify(vnode, addr).await;
+                Ok(None)
+            }
+            Message::PushKeys(entries) => {
+                let keys = entries.iter().map(|e| e.key.clone()).collect();
+                self.insert_entries(vnode, entries).await?;
+                Ok(Some(Message::KeysAck(keys)))
+            }
+            Message::Replicate(entries) => {
+                let keys = entries.iter().map(|e| e.key.clone()).collect();
+                Self::merge_entries(&mut *self.replicas.lock().await, entries)?;
+                Ok(Some(Message::KeysAck(keys)))
+            }
+            Message::DropReplicas(keys) => {
+                let parsed = Self::parse_keys(&keys)?;
+                self.replicas.lock().await.remove_all(&parsed)?;
+                debug!(keys = keys.len(), "dropped replicas");
+                Ok(None)
+            }
+            Message::GetPredecessor => {
+                let pred = *vnode.predecessor.lock().await;
+                let response = Message::PredecessorResponse(pred);
+                Ok(Some(response))
+            }
+            Message::GetSuccessor => {
+                let succ = *vnode.successor.lock().await;
+                let response = Message::SuccessorResponse(succ);
+                Ok(Some(response))
+            }
+            Message::GetSuccessorList => {
+                let response = Message::SuccessorListResponse(vnode.successors().await);
+                Ok(Some(response))
+            }
+            Message::LeaveSuccessor(new_succecessor) => {
+                // our successor left so we need to update it to the
+                // new given one
+                let mut successor = vnode.successor.lock().await;
+                // if given successor = self.successor, take self
+                let new_successor = if successor.id == new_succecessor.id {
+                    self.neighbor(vnode)
+                } else {
+                    new_succecessor
+                };
+                if *successor != new_successor {
+                    self.successor_changed(vnode, *successor, new_successor);
                 }
-                Message::LeavePredecessor(new_predecessor) => {
-                    // our predecessor left so we need to update it to the
-                    // new given one
-                    let mut pred = self.predecessor.lock().await;
-
-                    *pred = if !new_predecessor.is_none() && new_predecessor == pred.clone() {
-                        Some(Neighbor::new(self.address, self.web_address))
-                    } else {
-                        new_predecessor
-                    };
-
-                    Ok(None)
+                *successor = new_successor;
+                Ok(None)
+            }
+            Message::LeavePredecessor(new_predecessor) => {
+                // our predecessor left so we need to update it to the
+                // new given one
+                let mut pred = vnode.predecessor.lock().await;
+
+                let new_predecessor = if new_predecessor.is_some() && new_predecessor == *pred {
+                    Some(self.neighbor(vnode))
+                } else {
+                    new_predecessor
+                };
+                if *pred != new_predecessor {
+                    self.predecessor_changed(vnode, *pred, new_predecessor);
                 }
+                *pred = new_predecessor;
+
+                Ok(None)
+            }
 
-                Message::Ping => Ok(Some(Message::Pong)),
-                _ => panic!("this should not happen (incoming message: {:?})", msg),
+            Message::Ping => Ok(Some(Message::Pong)),
+            // errors of storage requests are sent back to the requesting node
+            Message::Get(k) => {
+                let result = match Self::parse_key(&k) {
+                    Ok(key) => self.lookup(key).await,
+                    Err(err) => Err(err),
+                };
+                let result = result
+                    .and_then(|v| v.as_ref().map(Self::encode_value).transpose())
+                    .map_err(RemoteError::from);
+                Ok(Some(Message::GetResult(result)))
+            }
+            Message::Put(k, v) => {
+                let result = match (Self::parse_key(&k), Self::decode_value(&v)) {
+                    (Ok(key), Ok(value)) => self.put(key, value).await,
+                    (Err(err), _) | (_, Err(err)) => Err(err),
+                };
+                Ok(Some(Message::PutAck(result.map_err(RemoteError::from))))
+            }
+            Message::Delete(k) => {
+                let result = match Self::parse_key(&k) {
+                    Ok(key) => self.delete(key).await,
+                    Err(err) => Err(err),
+                };
+                Ok(Some(Message::DeleteAck(result.map_err(RemoteError::from))))
+            }
+            msg => {
+                // responses and errors are never sent as requests
+                warn!(request = ?msg, "received unexpected message");
+                let err = RemoteError::Unexpected(format!("{:?}", msg));
+                Ok(Some(Message::Error(err)))
             }
         }
     }
 
+    // all virtual nodes join the ring that the given node is part of
+    #[instrument(skip_all, fields(node = %self.address, entry = %entry_node))]
     pub async fn join(&self, entry_node: SocketAddr) -> Result<(), MessageError> {
         if entry_node == self.address {
             // node does not need to join itself
             return Ok(());
         }
-        {
-            let mut pred = self.predecessor.lock().await;
-            *pred = None;
+        let neighbor = Neighbor::new(entry_node, entry_node, &self.config.network.id_space);
+        for vnode in self.vnodes.iter() {
+            let (new_succ, _, _) = neighbor.find_successor(self.net(), vnode.id, false).await?;
+            let old_pred = vnode.predecessor.lock().await.take();
+            let old_succ = std::mem::replace(&mut *vnode.successor.lock().await, new_succ);
+            if old_pred.is_some() {
+                self.predecessor_changed(vnode, old_pred, None);
+            }
+            if old_succ != new_succ {
+                self.successor_changed(vnode, old_succ, new_succ);
+            }
         }
+        self.events.emit(Event::Joined { entry: entry_node });
+        Ok(())
+    }
 
-        {
-            let neighbor = Neighbor::new(entry_node, entry_node);
-            let new_succ = neighbor.find_successor(self.id).await?;
-
-            let mut successor = self.successor.lock().await;
-            *successor = new_succ;
+    // hands keys over to a successor and deletes those it stored
+    async fn move_keys(
+        &self,
+        successor: Neighbor,
+        entries: Vec<Entry>,
+    ) -> Result<(), MessageError> {
+        if entries.is_empty() {
+            return Ok(());
         }
+        let stored = successor.push_keys(self.net(), entries).await?;
+        let stored_count = stored.len();
+        self.store
+            .lock()
+            .await
+            .remove_all(&Self::parse_keys(&stored)?)?;
+        info!(successor = %successor.addr, "moved keys to successor");
+        self.events.emit(Event::KeysMigrated {
+            from: self.address,
+            to: successor.addr,
+            keys: stored_count,
+        });
         Ok(())
     }
 
+    // the virtual nodes leave one after another, a virtual node whose
+    // successor is another virtual node of ours leaves its keys in the store,
+    // they are handed over to the first successor on another node at the end
+    #[instrument(skip_all, fields(node = %self.address))]
     pub async fn leave(&self) -> Result<(), MessageError> {
-        let p = self.predecessor.lock().await.clone();
-        let s = self.successor.lock().await.clone();
-
-        // we cannot await those communications
-        // since this leads to a deadlock if two neighboring nodes
-        // leave at the same time
-        // TODO maybe await somehow to allow for safe leave
-        if let Some(p2) = p {
+        for vnode in self.vnodes.iter() {
+            let p = *vnode.predecessor.lock().await;
+            let s = *vnode.successor.lock().await;
+
+            // hand over the keys to the successor, they are only
+            // deleted after the successor acknowledged them
+            if s.addr != self.address {
+                let entries: Vec<Entry> = self
+                    .store
+                    .lock()
+                    .await
+                    .iter()
+                    .filter(|(k, _)| {
+                        p.map(|p| self.key_id(k).is_between(p.id, vnode.id))
+                            .unwrap_or(true)
+                    })
+                    .map(|(k, r)| Self::to_entry(k, r))
+                    .collect::<Result<_, _>>()?;
+                self.move_keys(s, entries).await?;
+            }
+
+            // we cannot await those communications
+            // since this leads to a deadlock if two neighboring nodes
+            // leave at the same time
+            // TODO maybe await somehow to allow for safe leave
+            if let Some(p2) = p {
+                #[allow(unused_must_use)]
+                {
+                    p2.leave_successor(self.net(), s).await;
+                }
+            }
             #[allow(unused_must_use)]
             {
-                p2.leave_successor(s).await;
+                s.leave_predecessor(self.net(), p).await;
             }
         }
-        #[allow(unused_must_use)]
-        {
-            s.leave_predecessor(p).await;
+
+        // keys of virtual nodes that were followed by another one of ours and
+        // keys of no virtual node go to the first successor on another node
+        let mut remaining: HashMap<SocketAddr, (Neighbor, Vec<Entry>)> = HashMap::new();
+        let predecessors = self.predecessors().await;
+        let entries: Vec<(Identifier, Entry)> = self
+            .store
+            .lock()
+            .await
+            .iter()
+            .map(|(k, r)| Ok((self.key_id(k), Self::to_entry(k, r)?)))
+            .collect::<Result<_, MessageError>>()?;
+        for (id, entry) in entries {
+            let vnode = self
+                .range_owner(&predecessors, id)
+                .unwrap_or(&self.vnodes[0]);
+            let successors = vnode.successors().await;
+            if let Some(s) = successors.into_iter().find(|s| s.addr != self.address) {
+                remaining
+                    .entry(s.addr)
+                    .or_insert((s, Vec::new()))
+                    .1
+                    .push(entry);
+            }
+        }
+        for (s, entries) in remaining.into_values() {
+            self.move_keys(s, entries).await?;
         }
-        let mut pred = self.predecessor.lock().await;
-        let mut succ = self.successor.lock().await;
 
-        *pred = None;
-        *succ = Neighbor::new(self.address, self.web_address);
-        let mut second = self.second_successor.lock().await;
-        *second = None;
+        let ids: Vec<Identifier> = self.vnodes.iter().map(|v| v.id).collect();
+        for vnode in self.vnodes.iter() {
+            let (predecessor, successor) =
+                Self::local_ring(self.address, self.web_address, &ids, vnode.id);
+            *vnode.predecessor.lock().await = predecessor;
+            *vnode.successor.lock().await = successor;
+            vnode.successor_list.lock().await.clear();
+            *vnode.fingers.lock().await = vec![None; vnode.id.bits() as usize];
+        }
+        self.events.emit(Event::Left);
         Ok(())
     }
 
-    async fn find_successor(&self, id: Identifier) -> Result<Neighbor, MessageError> {
-        if self.contains_id(id).await {
-            Ok(Neighbor::new(self.address, self.web_address))
-        } else {
-            let succ = self.successor.lock().await.clone();
-            if succ.id == self.id {
-                Ok(succ)
-            } else {
-                Ok(succ.find_successor(id).await?)
+    async fn find_successor(
+        &self,
+        vnode: &VirtualNode,
+        id: Identifier,
+    ) -> Result<Neighbor, MessageError> {
+        self.find_successor_traced(vnode, id, &mut LookupPath::default())
+            .await
+    }
+
+    // the nodes the lookup is forwarded to are added to the path
+    async fn find_successor_traced(
+        &self,
+        vnode: &VirtualNode,
+        id: Identifier,
+        path: &mut LookupPath,
+    ) -> Result<Neighbor, MessageError> {
+        if vnode.contains_id(id).await {
+            return Ok(self.neighbor(vnode));
+        }
+        let succ = *vnode.successor.lock().await;
+        if succ.id == vnode.id || id.is_between(vnode.id, succ.id) {
+            return Ok(succ);
+        }
+        let next = self.closest_preceding_node(vnode, id).await;
+        if next.id == vnode.id || next.id == succ.id {
+            return self.forward_lookup(succ, id, path).await;
+        }
+        match self.forward_lookup(next, id, path).await {
+            Ok(n) => Ok(n),
+            Err(err) => {
+                // the finger is not reachable anymore, forget about it
+                // and fall back to the (slower) successor pointer
+                warn!(finger = %next.addr, error = ?err, "finger failed");
+                Self::remove_finger(vnode, next).await;
+                self.forward_lookup(succ, id, path).await
             }
         }
     }
 
-    async fn notify(&self, other: Neighbor) {
-        let mut pred = self.predecessor.lock().await;
+    // lets the node continue a recursive lookup
+    async fn forward_lookup(
+        &self,
+        to: Neighbor,
+        id: Identifier,
+        path: &mut LookupPath,
+    ) -> Result<Neighbor, MessageError> {
+        let start = Instant::now();
+        let result = to.find_successor(self.net(), id, path.is_traced()).await;
+        path.record(to, start.elapsed(), result.as_ref().err());
+        let (owner, count, later_hops) = result?;
+        path.extend(count, later_hops);
+        Ok(owner)
+    }
+
+    // returns the finger that most closely precedes the given id
+    // or the virtual node itself if there is none
+    async fn closest_preceding_node(&self, vnode: &VirtualNode, id: Identifier) -> Neighbor {
+        let fingers = vnode.fingers.lock().await;
+        for finger in fingers.iter().rev().flatten() {
+            if finger.id != id && finger.id.is_between(vnode.id, id) {
+                return *finger;
+            }
+        }
+        self.neighbor(vnode)
+    }
+
+    // finds the node responsible for the id, starting at the closest
+    // virtual node of ours
+    pub async fn find_owner(
+        &self,
+        id: Identifier,
+        mode: LookupMode,
+    ) -> Result<Neighbor, MessageError> {
+        let mut path = LookupPath::default();
+        let owner = self.find_owner_traced(id, mode, &mut path).await;
+        self.metrics.lookup_hops.observe(path.count as f64);
+        owner
+    }
+
+    // like find_owner, but also records every node the lookup visited
+    pub async fn trace(&self, id: Identifier, mode: LookupMode) -> LookupTrace {
+        let vnode = self.preceding_vnode(id);
+        let mut path = LookupPath::traced(vec![TraceHop {
+            node: self.neighbor(vnode),
+            latency: Duration::ZERO,
+            error: None,
+        }]);
+        let owner = self
+            .find_owner_traced(id, mode, &mut path)
+            .await
+            .map_err(|err| format!("{:?}", err));
+        let hops = path.hops.unwrap_or_default();
+        LookupTrace { hops, owner }
+    }
+
+    async fn find_owner_traced(
+        &self,
+        id: Identifier,
+        mode: LookupMode,
+        path: &mut LookupPath,
+    ) -> Result<Neighbor, MessageError> {
+        let vnode = self.preceding_vnode(id);
+        match mode {
+            LookupMode::Recursive => self.find_successor_traced(vnode, id, path).await,
+            LookupMode::Iterative => self.find_successor_iteratively(vnode, id, path).await,
+        }
+    }
+
+    // the owner of the id if the virtual node knows it, otherwise the
+    // nodes that are closer to the id
+    async fn next_hop(&self, vnode: &VirtualNode, id: Identifier) -> Hop {
+        if vnode.contains_id(id).await {
+            return Hop::Owner(self.neighbor(vnode));
+        }
+        let succ = *vnode.successor.lock().await;
+        if succ.id == vnode.id || id.is_between(vnode.id, succ.id) {
+            return Hop::Owner(succ);
+        }
+        let mut next = vec![self.closest_preceding_node(vnode, id).await];
+        for s in vnode.successors().await {
+            if next.iter().all(|n| n.id != s.id) {
+                next.push(s);
+            }
+        }
+        next.retain(|n| n.id != vnode.id);
+        Hop::Next(next)
+    }
+
+    // asks one hop after another for the next hop until one of them knows
+    // the owner of the id
+    // nodes that are closer to the id are tried first, a hop that fails or
+    // does not answer in time is skipped and the next candidate is tried
+    async fn find_successor_iteratively(
+        &self,
+        vnode: &VirtualNode,
+        id: Identifier,
+        path: &mut LookupPath,
+    ) -> Result<Neighbor, MessageError> {
+        let mut hop = self.neighbor(vnode);
+        let mut answer = self.next_hop(vnode, id).await;
+        let mut candidates: Vec<Neighbor> = Vec::new();
+        let mut tried = vec![hop.id];
+        let mut last_err = None;
+        loop {
+            match answer {
+                Hop::Owner(owner) => return Ok(owner),
+                Hop::Next(next) => {
+                    // nodes between the hop and the id bring us closer, nodes
+                    // after the id are only asked if everything else failed
+                    let (mut closer, beyond): (Vec<Neighbor>, Vec<Neighbor>) = next
+                        .into_iter()
+                        .filter(|n| !tried.contains(&n.id))
+                        .partition(|n| n.id.is_between(hop.id, id));
+                    closer.extend(candidates.drain(..).chain(beyond));
+                    for n in closer {
+                        if candidates.iter().all(|c| c.id != n.id) {
+                            candidates.push(n);
+                        }
+                    }
+                }
+            }
+            answer = loop {
+                if candidates.is_empty() {
+                    return Err(last_err.unwrap_or(MessageError::LookupFailed(id)));
+                }
+                let candidate = candidates.remove(0);
+                tried.push(candidate.id);
+                let request = candidate.closest_preceding(self.net(), id);
+                let start = Instant::now();
+                let result = match timeout(self.config.hop_timeout, request).await {
+                    Ok(result) => result,
+                    Err(_) => Err(MessageError::Timeout(candidate.addr)),
+                };
+                path.record(candidate, start.elapsed(), result.as_ref().err());
+                let err = match result {
+                    Ok(answer) => {
+                        hop = candidate;
+                        break answer;
+                    }
+                    Err(err) => err,
+                };
+                warn!(hop = %candidate.addr, error = ?err, "hop failed");
+                Self::remove_finger(vnode, candidate).await;
+                last_err = Some(err);
+            };
+        }
+    }
+
+    async fn remove_finger(vnode: &VirtualNode, neighbor: Neighbor) {
+        let mut fingers = vnode.fingers.lock().await;
+        for finger in fingers.iter_mut() {
+            if finger.map(|f| f.id == neighbor.id).unwrap_or(false) {
+                *finger = None;
+            }
+        }
+    }
+
+    // refreshes the next entry of the finger table of every virtual node
+    #[instrument(skip_all, fields(node = %self.address))]
+    pub async fn fix_fingers(&self) -> Result<(), MessageError> {
+        if *self.sim_crash_state.lock().await {
+            return Ok(());
+        }
+        let mut result = Ok(());
+        for vnode in self.vnodes.iter() {
+            if let Err(err) = self.fix_finger(vnode).await {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    // all following entries that point to the same node are updated as well
+    async fn fix_finger(&self, vnode: &VirtualNode) -> Result<(), MessageError> {
+        let i = {
+            let mut next = vnode.next_finger.lock().await;
+            let i = *next;
+            *next = (i + 1) % vnode.id.bits() as usize;
+            i
+        };
+        let start = vnode.id.offset(i);
+        let finger = self.find_successor(vnode, start).await?;
+
+        let mut fingers = vnode.fingers.lock().await;
+        fingers[i] = Some(finger);
+        let mut j = i + 1;
+        while j < fingers.len() && vnode.id.offset(j).is_between(vnode.id, finger.id) {
+            fingers[j] = Some(finger);
+            j += 1;
+        }
+        drop(fingers);
+        *vnode.next_finger.lock().await = j % vnode.id.bits() as usize;
+        Ok(())
+    }
+
+    // returns whether the given node was accepted as new predecessor
+    async fn notify(&self, vnode: &VirtualNode, other: Neighbor) -> bool {
+        let mut pred = vnode.predecessor.lock().await;
         match pred.as_mut() {
             Some(predecessor) => {
-                if other.id.is_between(predecessor.id, self.id) && predecessor.id != other.id {
-                    println!("[{:}] updated predecessor to {:}", self, other.addr);
-                    *predecessor = other
+                if other.id.is_between(predecessor.id, vnode.id) && predecessor.id != other.id {
+                    info!(predecessor = %other.addr, "updated predecessor");
+                    self.predecessor_changed(vnode, Some(*predecessor), Some(other));
+                    *predecessor = other;
+                    return true;
                 }
+                false
             }
             None => {
-                println!("[{:}] updated predecessor to {:}", self, other.addr);
-                *pred = Some(other)
+                info!(predecessor = %other.addr, "updated predecessor");
+                *pred = Some(other);
+                self.predecessor_changed(vnode, None, Some(other));
+                true
+            }
+        }
+    }
+
+    // stores migrated key-value pairs and replicates them to our successors
+    async fn insert_entries(
+        &self,
+        vnode: &VirtualNode,
+        entries: Vec<Entry>,
+    ) -> Result<(), MessageError> {
+        Self::merge_entries(&mut *self.store.lock().await, entries.clone())?;
+        self.replicate(vnode, entries).await;
+        Ok(())
+    }
+
+    // the nodes that store copies of the keys of the virtual node,
+    // other virtual nodes of ours share the store and are skipped
+    async fn replica_targets(&self, vnode: &VirtualNode) -> Vec<Neighbor> {
+        self.pick_replica_targets(self.address, vnode.successors().await)
+    }
+
+    // the first k-1 of the given successors of a node at addr that are
+    // other nodes, each node only counts once
+    fn pick_replica_targets(&self, addr: SocketAddr, successors: Vec<Neighbor>) -> Vec<Neighbor> {
+        let mut targets: Vec<Neighbor> = Vec::new();
+        for n in successors {
+            if n.addr != addr && targets.iter().all(|t| t.addr != n.addr) {
+                targets.push(n);
             }
         }
+        targets.truncate(self.config.replication_factor.saturating_sub(1));
+        targets
+    }
+
+    // writes the given pairs to the next k-1 successors
+    // a target that failed is forgotten as a replica holder, so the next
+    // re-replication writes all keys of the virtual node to it again
+    async fn replicate(&self, vnode: &VirtualNode, entries: Vec<Entry>) {
+        if entries.is_empty() {
+            return;
+        }
+        for target in self.replica_targets(vnode).await {
+            if let Err(err) = target.replicate(self.net(), entries.clone()).await {
+                warn!(target = %target.addr, error = ?err, "could not replicate");
+                vnode
+                    .replica_holders
+                    .lock()
+                    .await
+                    .retain(|h| h.addr != target.addr);
+            }
+        }
+    }
+
+    // tells nodes that no longer hold copies of the given keys for us to
+    // delete them, so they are neither kept forever nor promoted once stale
+    async fn drop_replicas(&self, holders: Vec<Neighbor>, keys: Vec<String>) {
+        if keys.is_empty() {
+            return;
+        }
+        for holder in holders {
+            if let Err(err) = holder.drop_replicas(self.net(), keys.clone()).await {
+                warn!(holder = %holder.addr, error = ?err, "could not drop replicas");
+            }
+        }
+    }
+
+    // moves replicas of keys that the virtual node is now responsible for
+    // into the store, returns the number of promoted replicas
+    async fn promote_replicas(&self, vnode: &VirtualNode) -> Result<usize, MessageError> {
+        let predecessor = match *vnode.predecessor.lock().await {
+            Some(p) => p,
+            None => return Ok(0),
+        };
+        let promoted: Vec<String> = self
+            .replicas
+            .lock()
+            .await
+            .iter()
+            .filter(|(k, _)| self.key_id(k).is_between(predecessor.id, vnode.id))
+            .map(|(k, _)| k.to_string())
+            .collect();
+        if promoted.is_empty() {
+            return Ok(0);
+        }
+        let mut replicas = self.replicas.lock().await;
+        let mut store = self.store.lock().await;
+        let mut newer = Vec::new();
+        for k in promoted.iter() {
+            let key = Self::parse_key(k)?;
+            if let Some(record) = replicas.get(&key) {
+                if record.is_newer_than(store.get(&key).as_ref()) {
+                    newer.push((key, record));
+                }
+            }
+        }
+        store.insert_all(newer)?;
+        replicas.remove_all(&Self::parse_keys(&promoted)?)?;
+        info!(keys = promoted.len(), "promoted replicas to primaries");
+        self.events.emit(Event::ReplicasPromoted {
+            vnode: vnode.id,
+            keys: promoted.len(),
+        });
+        Ok(promoted.len())
+    }
+
+    // restores k copies of the keys of the virtual node if its replica
+    // holders changed, keys of no virtual node are replicated by the first one
+    async fn rereplicate(&self, vnode: &VirtualNode, force: bool) -> Result<(), MessageError> {
+        let targets = self.replica_targets(vnode).await;
+        let dropped: Vec<Neighbor> = {
+            let mut holders = vnode.replica_holders.lock().await;
+            if !force && *holders == targets {
+                return Ok(());
+            }
+            let old = std::mem::replace(&mut *holders, targets);
+            old.into_iter()
+                .filter(|h| holders.iter().all(|t| t.addr != h.addr))
+                .collect()
+        };
+        let predecessors = self.predecessors().await;
+        let entries: Vec<Entry> = self
+            .store
+            .lock()
+            .await
+            .iter()
+            .filter(|(k, _)| {
+                let owner = self.range_owner(&predecessors, self.key_id(k));
+                owner.unwrap_or(&self.vnodes[0]).id == vnode.id
+            })
+            .map(|(k, r)| Self::to_entry(k, r))
+            .collect::<Result<_, _>>()?;
+        let keys = entries.iter().map(|e| e.key.clone()).collect();
+        self.drop_replicas(dropped, keys).await;
+        self.replicate(vnode, entries).await;
+        Ok(())
     }
 
+    #[instrument(skip_all, fields(node = %self.address))]
     pub async fn stabilize(&self) -> Result<(), MessageError> {
-        if self.sim_crash_state.lock().await.clone() {
+        if *self.sim_crash_state.lock().await {
             return Ok(());
         }
-        let successor = self.successor.lock().await.clone();
+        let start = Instant::now();
+        let result = self.stabilize_vnodes().await;
+        self.metrics
+            .stabilization_duration
+            .observe_duration(start.elapsed());
+        if result.is_err() {
+            self.metrics.stabilization_errors.inc();
+        }
+        result
+    }
 
-        let predecessor = if self.id != successor.id {
-            successor.get_predecessor().await?
+    async fn stabilize_vnodes(&self) -> Result<(), MessageError> {
+        let mut result = Ok(());
+        for vnode in self.vnodes.iter() {
+            if let Err(err) = self.stabilize_vnode(vnode).await {
+                result = Err(err);
+            }
+        }
+        // keys are handed off even if a virtual node could not be stabilized
+        let handed_off = self.hand_off_keys().await;
+        result.and(handed_off)
+    }
+
+    #[instrument(skip_all, fields(vnode = %format_args!("{:x}", vnode.id)))]
+    async fn stabilize_vnode(&self, vnode: &VirtualNode) -> Result<(), MessageError> {
+        let successor = *vnode.successor.lock().await;
+
+        let predecessor = if vnode.id != successor.id {
+            successor.get_predecessor(self.net()).await?
         } else {
-            self.predecessor.lock().await.clone()
+            *vnode.predecessor.lock().await
         };
         if let Some(x) = predecessor {
-            if x.id.is_between(self.id, successor.id) && successor.id != x.id {
-                let mut successor = self.successor.lock().await;
-                *successor = x;
+            if x.id.is_between(vnode.id, successor.id) && successor.id != x.id {
+                *vnode.successor.lock().await = x;
+                self.successor_changed(vnode, successor, x);
             }
         }
         // the node does not need to message itself
-        if self.id != successor.id {
-            successor
-                .notify(Neighbor::new(self.address, self.web_address))
-                .await?;
+        if vnode.id != successor.id {
+            successor.notify(self.net(), self.neighbor(vnode)).await?;
+        } else if predecessor.is_none() {
+            // alone on the ring, the node is its own predecessor
+            self.notify(vnode, self.neighbor(vnode)).await;
         }
         Ok(())
     }
 
-    pub async fn put(&self, key: Key, value: Value) -> Result<(), MessageError> {
-        let id = key.hash_id();
-        if !self.contains_id(id).await {
-            let succ = self.successor.lock().await.clone();
-            let addr = succ.find_successor(id).await?;
-            let client = Client::new();
-
-            let url: Uri = format!("http://{:}/storage/{:}", addr.web_addr, key.to_string())
-                .parse()
-                .unwrap();
-
-            let payload = warp::hyper::body::Body::from(value.to_string());
-            let req = http::Request::builder()
-                .uri(url)
-                .method(http::Method::PUT)
-                .body(payload)
-                .unwrap();
-            let res = client.request(req).await.unwrap();
-            return match res.status() {
-                http::StatusCode::OK => Ok(()),
-                status => Err(MessageError::HTTPStatusError(status)),
+    // keys can end up on a node that is not responsible for them if the ring
+    // changes, e.g. when a new predecessor joined, those are pushed to the
+    // predecessor of the virtual node they precede, which either owns them
+    // or passes them on to its own predecessor
+    async fn hand_off_keys(&self) -> Result<(), MessageError> {
+        // a slow hand-off must not be started again by the next stabilize run
+        let _running = match self.handing_off.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => return Ok(()),
+        };
+        let predecessors = self.predecessors().await;
+        if predecessors.iter().any(Option::is_none) {
+            return Ok(());
+        }
+        let mut by_vnode: HashMap<Identifier, Vec<Entry>> = HashMap::new();
+        for (k, r) in self.store.lock().await.iter() {
+            let id = self.key_id(k);
+            if self.range_owner(&predecessors, id).is_none() {
+                by_vnode
+                    .entry(self.following_vnode(id).id)
+                    .or_default()
+                    .push(Self::to_entry(k, r)?);
+            }
+        }
+        let mut result = Ok(());
+        for (vnode, predecessor) in self.vnodes.iter().zip(predecessors) {
+            let (entries, owner) = match (by_vnode.remove(&vnode.id), predecessor) {
+                (Some(entries), Some(owner)) if owner.addr != self.address => (entries, owner),
+                _ => continue,
             };
+            if let Err(err) = self.hand_off(vnode, owner, entries).await {
+                result = Err(err);
+            }
         }
-        self.store.lock().await.insert(key, value);
+        result
+    }
+
+    async fn hand_off(
+        &self,
+        vnode: &VirtualNode,
+        owner: Neighbor,
+        entries: Vec<Entry>,
+    ) -> Result<(), MessageError> {
+        let stored = owner.push_keys(self.net(), entries).await?;
+        info!(keys = stored.len(), owner = %owner.addr, "handed off keys");
+        self.events.emit(Event::KeysMigrated {
+            from: self.address,
+            to: owner.addr,
+            keys: stored.len(),
+        });
+        self.store
+            .lock()
+            .await
+            .remove_all(&Self::parse_keys(&stored)?)?;
+        self.drop_handed_off_replicas(vnode, &owner, stored).await;
+        Ok(())
+    }
+
+    // the copies of keys we handed off are only kept by those of our replica
+    // holders the new owner may replicate to as well, which are among the
+    // first k-1 nodes of our vnode and its successors
+    async fn drop_handed_off_replicas(
+        &self,
+        vnode: &VirtualNode,
+        owner: &Neighbor,
+        keys: Vec<String>,
+    ) {
+        let mut successors = vec![self.neighbor(vnode)];
+        successors.extend(vnode.successors().await);
+        let targets = self.pick_replica_targets(owner.addr, successors);
+        let holders: Vec<Neighbor> = vnode
+            .replica_holders
+            .lock()
+            .await
+            .iter()
+            .filter(|h| targets.iter().all(|t| t.addr != h.addr))
+            .copied()
+            .collect();
+        self.drop_replicas(holders, keys).await;
+    }
+
+    pub async fn put(&self, key: Key, value: Value) -> Result<(), MessageError> {
+        self.put_with(key, value, self.config.lookup_mode).await
+    }
+
+    #[instrument(skip_all, fields(node = %self.address, key = %key.to_string()))]
+    pub async fn put_with(
+        &self,
+        key: Key,
+        value: Value,
+        mode: LookupMode,
+    ) -> Result<(), MessageError> {
+        if let Some(owner) = self.remote_owner(&key, mode).await? {
+            let net = self.net();
+            return owner
+                .put(net, key.to_string(), Self::encode_value(&value)?)
+                .await;
+        }
+        self.write(key, Some(value)).await?;
+        Ok(())
+    }
+
+    // deletes the key from the chord ring, returns whether it existed
+    pub async fn delete(&self, key: Key) -> Result<bool, MessageError> {
+        self.delete_with(key, self.config.lookup_mode).await
+    }
+
+    #[instrument(skip_all, fields(node = %self.address, key = %key.to_string()))]
+    pub async fn delete_with(&self, key: Key, mode: LookupMode) -> Result<bool, MessageError> {
+        if let Some(owner) = self.remote_owner(&key, mode).await? {
+            return owner.delete(self.net(), key.to_string()).await;
+        }
+        // deleted keys are kept as tombstone
+        let existed = self.write(key, None).await?;
+        Ok(existed)
+    }
+
+    // stores a new version of a key we are responsible for and replicates it
+    // returns whether the key had a value before
+    async fn write(&self, key: Key, value: Option<Value>) -> Result<bool, MessageError> {
+        let predecessors = self.predecessors().await;
+        let vnode = self
+            .range_owner(&predecessors, self.key_id(&key))
+            .unwrap_or(&self.vnodes[0]);
+        let (entry, existed) = {
+            let mut store = self.store.lock().await;
+            let current = store.get(&key);
+            let existed = current.as_ref().map(|r| r.value.is_some()).unwrap_or(false);
+            let record = Record {
+                version: Self::next_version(current.as_ref()),
+                value,
+            };
+            let entry = Self::to_entry(&key, &record)?;
+            store.insert(key, record)?;
+            (entry, existed)
+        };
+        self.replicate(vnode, vec![entry]).await;
+        Ok(existed)
+    }
+
+    // persists the store and replicas if the storage backend supports it
+    pub async fn snapshot(&self) -> Result<(), MessageError> {
+        self.store.lock().await.snapshot()?;
+        self.replicas.lock().await.snapshot()?;
         Ok(())
     }
 
     pub async fn sim_crash(&self) -> Result<(), MessageError> {
         let mut scs = self.sim_crash_state.lock().await;
         *scs = true;
+        self.events.emit(Event::Crashed);
         Ok(())
     }
 
     pub async fn sim_recover(&self) -> Result<(), MessageError> {
         let mut scs = self.sim_crash_state.lock().await;
         *scs = false;
+        self.events.emit(Event::Recovered);
         Ok(())
     }
 
     pub async fn is_crashed(&self) -> bool {
-        return self.sim_crash_state.lock().await.clone();
-    }
-
-    pub async fn check_successors(&self) {
-        let successor = self.successor.lock().await.clone();
-        match successor.get_succcessor().await {
-            Ok(s) => {
-                let mut second_successor = self.second_successor.lock().await;
-                if s.id != self.id && s.id != successor.id {
-                    if (second_successor.is_some() && second_successor.unwrap().id != s.id)
-                        || second_successor.is_none()
-                    {
-                        *second_successor = Some(s);
-                        println!(
-                            "[{:}] updated second successor to {:}",
-                            self.address, s.addr
-                        );
+        return *self.sim_crash_state.lock().await;
+    }
+
+    // pings the predecessors and clears those that do not answer in time
+    // so that notify can accept the correct ones
+    #[instrument(skip_all, fields(node = %self.address))]
+    pub async fn check_predecessor(&self) {
+        for vnode in self.vnodes.iter() {
+            self.check_vnode_predecessor(vnode).await;
+        }
+    }
+
+    async fn check_vnode_predecessor(&self, vnode: &VirtualNode) {
+        let predecessor = match *vnode.predecessor.lock().await {
+            Some(p) if p.id != vnode.id => p,
+            _ => return,
+        };
+        let alive = matches!(
+            timeout(self.config.ping_timeout, predecessor.ping(self.net())).await,
+            Ok(Ok(()))
+        );
+        if alive {
+            return;
+        }
+        let mut pred = vnode.predecessor.lock().await;
+        // the predecessor might have changed in the meantime
+        if *pred == Some(predecessor) {
+            warn!(vnode = %format_args!("{:x}", vnode.id), predecessor = %predecessor.addr, "predecessor failed");
+            *pred = None;
+            self.predecessor_changed(vnode, Some(predecessor), None);
+            *vnode.failed_predecessor.lock().await = Some(predecessor);
+            *vnode.predecessor_failures.lock().await += 1;
+        }
+    }
+
+    // returns the successors of all virtual nodes
+    pub async fn successors(&self) -> Vec<Neighbor> {
+        let mut successors = Vec::new();
+        for vnode in self.vnodes.iter() {
+            successors.extend(vnode.successors().await);
+        }
+        successors
+    }
+
+    // counts the change and tells the subscribers of the events
+    fn predecessor_changed(
+        &self,
+        vnode: &VirtualNode,
+        old: Option<Neighbor>,
+        new: Option<Neighbor>,
+    ) {
+        self.metrics.predecessor_changes.inc();
+        self.events.emit(Event::PredecessorChanged {
+            vnode: vnode.id,
+            old,
+            new,
+        });
+    }
+
+    fn successor_changed(&self, vnode: &VirtualNode, old: Neighbor, new: Neighbor) {
+        self.metrics.successor_changes.inc();
+        self.events.emit(Event::SuccessorChanged {
+            vnode: vnode.id,
+            old,
+            new,
+        });
+    }
+
+    // walks the successor chain from our virtual nodes and asks every node
+    // for its predecessor and successors, the walk continues from nodes that
+    // were only mentioned by others so that separate cycles are found too
+    #[instrument(skip_all, fields(node = %self.address))]
+    pub async fn ring(&self) -> Ring {
+        let mut nodes = Vec::new();
+        let mut unreachable = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending: VecDeque<Neighbor> =
+            self.vnodes.iter().map(|v| self.neighbor(v)).collect();
+        while let Some(start) = pending.pop_front() {
+            let mut next = Some(start);
+            while let Some(n) = next.take() {
+                if seen.len() >= MAX_RING_NODES || !seen.insert(n.id) {
+                    break;
+                }
+                match self.ring_entry(n).await {
+                    Ok(entry) => {
+                        next = entry.successor();
+                        pending.extend(entry.predecessor);
+                        pending.extend(entry.successors.iter().skip(1));
+                        nodes.push(entry);
+                    }
+                    Err(err) => {
+                        debug!(peer = %n.addr, error = ?err, "node did not describe itself");
+                        unreachable.push((n, format!("{:?}", err)));
                     }
                 }
             }
-            Err(_) => {
-                println!("[{:}] successor failed", self.address);
-                let mut second_successor = self.second_successor.lock().await;
-                if let Some(s) = second_successor.clone() {
-                    let mut succ = self.successor.lock().await;
-                    *succ = s;
-                    *second_successor = None;
-                    println!(
-                        "[{:}] set successor to second successor {:}",
-                        self.address, s.addr
-                    );
+        }
+        Ring::new(nodes, unreachable)
+    }
+
+    // whether the successors form a single cycle over all nodes the walk
+    // found and every node is the predecessor of its successor
+    pub async fn is_stable(&self) -> bool {
+        self.ring().await.is_consistent()
+    }
+
+    // walks the ring every period until it is consistent or max_wait passed,
+    // the ring only changes while the periodic maintenance runs meanwhile
+    pub async fn wait_until_stable(&self, max_wait: Duration, period: Duration) -> Stabilization {
+        let start = Instant::now();
+        let mut checks = 0;
+        loop {
+            let ring = self.ring().await;
+            checks += 1;
+            let elapsed = start.elapsed();
+            if ring.is_consistent() || elapsed >= max_wait {
+                return Stabilization {
+                    stable: ring.is_consistent(),
+                    elapsed,
+                    checks,
+                    ring,
+                };
+            }
+            sleep(period.min(max_wait - elapsed)).await;
+        }
+    }
+
+    async fn ring_entry(&self, node: Neighbor) -> Result<RingEntry, MessageError> {
+        let predecessor = node.get_predecessor(self.net()).await?;
+        let successors = node.get_successor_list(self.net()).await?;
+        Ok(RingEntry {
+            node,
+            predecessor,
+            successors,
+        })
+    }
+
+    // updates the successor lists from the lists of the direct successors
+    // and fails over to the first alive entry if a successor is dead
+    #[instrument(skip_all, fields(node = %self.address))]
+    pub async fn check_successors(&self) -> Result<(), MessageError> {
+        let mut result = Ok(());
+        for vnode in self.vnodes.iter() {
+            if let Err(err) = self.check_vnode_successors(vnode).await {
+                result = Err(err);
+            }
+        }
+        result
+    }
+
+    #[instrument(skip_all, fields(vnode = %format_args!("{:x}", vnode.id)))]
+    async fn check_vnode_successors(&self, vnode: &VirtualNode) -> Result<(), MessageError> {
+        let successor = *vnode.successor.lock().await;
+        if successor.id == vnode.id {
+            vnode.successor_list.lock().await.clear();
+            return Ok(());
+        }
+        let result = self.update_successors(vnode, successor).await;
+        let promoted = self.promote_replicas(vnode).await?;
+        self.rereplicate(vnode, promoted > 0).await?;
+        result
+    }
+
+    async fn update_successors(
+        &self,
+        vnode: &VirtualNode,
+        successor: Neighbor,
+    ) -> Result<(), MessageError> {
+        match successor.get_successor_list(self.net()).await {
+            Ok(list) => {
+                self.update_successor_list(vnode, successor, list).await;
+                Ok(())
+            }
+            Err(err) => {
+                warn!(successor = %successor.addr, error = ?err, "successor failed");
+                let candidates = vnode.successor_list.lock().await.clone();
+                for candidate in candidates {
+                    if candidate.id == vnode.id {
+                        break;
+                    }
+                    match candidate.get_successor_list(self.net()).await {
+                        Ok(list) => {
+                            *vnode.successor.lock().await = candidate;
+                            self.successor_changed(vnode, successor, candidate);
+                            self.events.emit(Event::Failover {
+                                vnode: vnode.id,
+                                failed: successor,
+                                successor: candidate,
+                            });
+                            info!(successor = %candidate.addr, "set successor");
+                            self.update_successor_list(vnode, candidate, list).await;
+                            return Ok(());
+                        }
+                        Err(err) => {
+                            warn!(successor = %candidate.addr, error = ?err, "backup successor failed");
+                        }
+                    }
                 }
+                Err(MessageError::AllSuccessorsDead(successor.addr))
+            }
+        }
+    }
+
+    // reconciles the successor list with the successor list of our successor
+    // a node with many virtual nodes would fill the list otherwise and leave
+    // no backup if it fails, so it contains at most one virtual node per node
+    async fn update_successor_list(
+        &self,
+        vnode: &VirtualNode,
+        successor: Neighbor,
+        list: Vec<Neighbor>,
+    ) {
+        let mut new_list: Vec<Neighbor> = Vec::new();
+        for n in list
+            .into_iter()
+            .take_while(|n| n.id != vnode.id && n.id != successor.id)
+        {
+            if n.addr != successor.addr && new_list.iter().all(|m| m.addr != n.addr) {
+                new_list.push(n);
             }
         }
+        new_list.truncate(self.config.successor_list_length.saturating_sub(1));
+        let mut successor_list = vnode.successor_list.lock().await;
+        if *successor_list != new_list {
+            debug!(
+                successors = ?new_list.iter().map(|n| n.addr).collect::<Vec<SocketAddr>>(),
+                "updated successor list"
+            );
+            *successor_list = new_list;
+        }
     }
 }
 
 impl<Key, Value> Display for Node<Key, Value>
 where
-    Key: Eq + Hash + HashIdentifier<Identifier> + ToString,
-    Value: Clone + FromStr + ToString,
-    <Value as FromStr>::Err: fmt::Debug,
+    Key: Eq + Hash + HashIdentifier + FromStr + ToString + Send + 'static,
+    Value: Clone + Serialize + DeserializeOwned + Send + 'static,
+    <Key as FromStr>::Err: fmt::Debug,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(&self.address.to_string())
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::node::Neighbor;
+
+// a virtual node as it described itself while the ring was walked
+#[derive(Debug, Clone)]
+pub struct RingEntry {
+    pub node: Neighbor,
+    pub predecessor: Option<Neighbor>,
+    // successor list, starting with the direct successor
+    pub successors: Vec<Neighbor>,
+}
+
+impl RingEntry {
+    pub fn successor(&self) -> Option<Neighbor> {
+        self.successors.first().copied()
+    }
+
+    // part of the ring the node is responsible for, not known while
+    // it has no predecessor
+    pub fn range_share(&self) -> Option<f64> {
+        self.predecessor.map(|p| {
+            if p.id == self.node.id {
+                1.0
+            } else {
+                (self.node.id - p.id).fraction()
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inconsistency {
+    // the successor pointers form the given number of cycles instead of one
+    Cycles(usize),
+    // following the successors of the node never leads back to it
+    NotOnCycle(Neighbor),
+    // the successor of the node has another predecessor
+    Asymmetric {
+        node: Neighbor,
+        successor: Neighbor,
+        predecessor: Option<Neighbor>,
+    },
+    // another node refers to the node but it did not answer
+    Unreachable(Neighbor),
+}
+
+// the ring as seen by walking the successor pointers
+#[derive(Debug, Clone)]
+pub struct Ring {
+    // in the order they were walked, starting with the successor chain
+    // of the node that walked the ring
+    pub nodes: Vec<RingEntry>,
+    pub unreachable: Vec<(Neighbor, String)>,
+    // cycles of the successor pointers, each in the order of the successors
+    pub cycles: Vec<Vec<Neighbor>>,
+    pub inconsistencies: Vec<Inconsistency>,
+}
+
+impl Ring {
+    pub fn new(nodes: Vec<RingEntry>, unreachable: Vec<(Neighbor, String)>) -> Self {
+        let index: HashMap<_, _> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.node.id, i))
+            .collect();
+        let successor = |i: usize| nodes[i].successor().and_then(|s| index.get(&s.id).copied());
+
+        // follows the successors from every node, a walk that comes back to
+        // a node it visited itself found a cycle
+        let mut walked_from = vec![None; nodes.len()];
+        let mut on_cycle = vec![false; nodes.len()];
+        let mut cycles = Vec::new();
+        for start in 0..nodes.len() {
+            let mut path: Vec<usize> = Vec::new();
+            let mut next = Some(start);
+            while let Some(i) = next {
+                if let Some(walk) = walked_from[i] {
+                    if walk == start {
+                        let pos = path.iter().position(|j| *j == i).unwrap();
+                        for j in &path[pos..] {
+                            on_cycle[*j] = true;
+                        }
+                        cycles.push(path[pos..].iter().map(|j| nodes[*j].node).collect());
+                    }
+                    break;
+                }
+                walked_from[i] = Some(start);
+                path.push(i);
+                next = successor(i);
+            }
+        }
+
+        let mut inconsistencies = Vec::new();
+        if cycles.len() != 1 {
+            inconsistencies.push(Inconsistency::Cycles(cycles.len()));
+        }
+        for (i, entry) in nodes.iter().enumerate() {
+            if !on_cycle[i] {
+                inconsistencies.push(Inconsistency::NotOnCycle(entry.node));
+            }
+            if let Some(j) = successor(i) {
+                let predecessor = nodes[j].predecessor;
+                if predecessor.map(|p| p.id) != Some(entry.node.id) {
+                    inconsistencies.push(Inconsistency::Asymmetric {
+                        node: entry.node,
+                        successor: nodes[j].node,
+                        predecessor,
+                    });
+                }
+            }
+        }
+        for (node, _) in unreachable.iter() {
+            inconsistencies.push(Inconsistency::Unreachable(*node));
+        }
+
+        Ring {
+            nodes,
+            unreachable,
+            cycles,
+            inconsistencies,
+        }
+    }
+
+    // whether the successors form a single cycle over all known nodes
+    // and every node is the predecessor of its successor
+    pub fn is_consistent(&self) -> bool {
+        self.inconsistencies.is_empty()
+    }
+}
+
+// outcome of waiting for the ring to become consistent
+#[derive(Debug, Clone)]
+pub struct Stabilization {
+    pub stable: bool,
+    // time until the ring was found consistent or the wait gave up
+    pub elapsed: Duration,
+    // number of walks of the ring
+    pub checks: usize,
+    // the last walk
+    pub ring: Ring,
+}
@@ -1,13 +1,50 @@
 use num_bigint::BigUint;
-use serde::{Deserialize, Serialize};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_bytes::ByteBuf;
+use sha1::Sha1;
 use sha2::{Digest, Sha256};
-use std::fmt::{Debug, Display, Formatter};
+use std::fmt::{Debug, Display, Formatter, LowerHex};
 use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::ops::{Add, Sub};
-#[derive(Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
-pub struct Identifier(u64);
+use std::str::FromStr;
+
+// identifiers are at most 160 bits wide
+const MAX_BYTES: usize = 20;
+
+// widths an identifier ring can have
+pub const SUPPORTED_BITS: &[u32] = &[64, 128, 160];
+
+// a position on a ring with 2^bits positions
+// the value is stored big endian in the last `len` bytes, so that the derived
+// ordering is the numeric one for identifiers of the same width
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Identifier {
+    len: u8,
+    bytes: [u8; MAX_BYTES],
+}
 
 impl Identifier {
+    // takes the first bits of the digest, the digest has to be long enough
+    pub fn from_digest(bits: u32, digest: &[u8]) -> Self {
+        let len = bits as usize / 8;
+        let mut bytes = [0u8; MAX_BYTES];
+        bytes[MAX_BYTES - len..].copy_from_slice(&digest[..len]);
+        Identifier {
+            len: len as u8,
+            bytes,
+        }
+    }
+
+    // width of the ring the identifier belongs to
+    pub fn bits(&self) -> u32 {
+        self.len as u32 * 8
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.bytes[MAX_BYTES - self.len as usize..]
+    }
+
     // Returns whether this identifier is between `start` (exclusive) and `end` (inclusive) on the
     // identifier ring
     pub fn is_between(&self, start: Identifier, end: Identifier) -> bool {
@@ -25,44 +62,63 @@ impl Identifier {
         if start > end && *self > start {
             return true;
         }
-        return false;
+        false
     }
-}
 
-impl Display for Identifier {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.0.to_string())
+    // Returns the identifier that is 2^i steps ahead of this one on the ring
+    // (the start of the i-th finger)
+    pub fn offset(&self, i: usize) -> Identifier {
+        let mut step = Identifier {
+            len: self.len,
+            bytes: [0u8; MAX_BYTES],
+        };
+        step.bytes[MAX_BYTES - 1 - i / 8] = 1 << (i % 8);
+        *self + step
+    }
+
+    // position on the ring as a fraction of the whole ring
+    pub fn fraction(&self) -> f64 {
+        self.value()
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, b)| *b as f64 * 2f64.powi(8 * i as i32))
+            .sum::<f64>()
+            / 2f64.powi(self.bits() as i32)
     }
 }
 
-impl From<u64> for Identifier {
-    fn from(value: u64) -> Self {
-        Identifier(value)
+impl Display for Identifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&BigUint::from_bytes_be(self.value()).to_string())
     }
 }
 
-impl From<Identifier> for u64 {
-    fn from(value: Identifier) -> Self {
-        value.0
+impl LowerHex for Identifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        LowerHex::fmt(&BigUint::from_bytes_be(self.value()), f)
     }
 }
 
-impl From<&[u8]> for Identifier {
-    fn from(bytes: &[u8]) -> Self {
-        let digest = Sha256::digest(bytes);
-        let id = BigUint::from_bytes_le(digest.as_slice());
-        let ring = id % std::u64::MAX;
-        Identifier(*ring.to_u64_digits().first().unwrap() as u64)
+impl From<u64> for Identifier {
+    fn from(value: u64) -> Self {
+        Identifier::from_digest(64, &value.to_be_bytes())
     }
 }
 
 impl Add for Identifier {
     type Output = Self;
 
+    // wraps around at the end of the ring of the left identifier
     fn add(self, other: Self) -> Self {
-        let (sum, _) = self.0.overflowing_add(other.0);
-
-        Identifier(sum)
+        let mut sum = self;
+        let mut carry = 0u16;
+        for i in (MAX_BYTES - self.len as usize..MAX_BYTES).rev() {
+            let s = self.bytes[i] as u16 + other.bytes[i] as u16 + carry;
+            sum.bytes[i] = s as u8;
+            carry = s >> 8;
+        }
+        sum
     }
 }
 
@@ -70,51 +126,175 @@ impl Sub for Identifier {
     type Output = Self;
 
     fn sub(self, other: Self) -> Self {
-        let (diff, _) = self.0.overflowing_sub(other.0);
-
-        Identifier(diff)
+        let mut diff = self;
+        let mut borrow = 0i16;
+        for i in (MAX_BYTES - self.len as usize..MAX_BYTES).rev() {
+            let d = self.bytes[i] as i16 - other.bytes[i] as i16 - borrow;
+            diff.bytes[i] = d as u8;
+            borrow = (d < 0) as i16;
+        }
+        diff
     }
 }
 
+// only the first 16 bits, enough to tell the nodes of a test ring apart
 impl Debug for Identifier {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        return f.write_fmt(format_args!("{:}", self.0 / 281474976710656));
+        let value = self.value();
+        f.write_fmt(format_args!("{:02x}{:02x}", value[0], value[1]))
+    }
+}
+
+// sent as a byte string of the width of the ring
+impl Serialize for Identifier {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.value())
+    }
+}
+
+impl<'de> Deserialize<'de> for Identifier {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = ByteBuf::deserialize(deserializer)?;
+        if !SUPPORTED_BITS.contains(&(bytes.len() as u32 * 8)) {
+            return Err(D::Error::custom(format!(
+                "invalid identifier length {:}",
+                bytes.len()
+            )));
+        }
+        Ok(Identifier::from_digest(bytes.len() as u32 * 8, &bytes))
+    }
+}
+
+// a hash function that identifiers are derived from
+pub trait IdentifierHash {
+    // width of the identifiers in bits
+    fn bits(&self) -> u32;
+
+    fn digest(&self, bytes: &[u8]) -> Vec<u8>;
+
+    // the identifier of the bytes is the beginning of their digest
+    fn identifier(&self, bytes: &[u8]) -> Identifier {
+        Identifier::from_digest(self.bits(), &self.digest(bytes))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashFunction {
+    Sha1,
+    Sha256,
+    Blake3,
+}
+
+impl HashFunction {
+    // all of them produce at least 160 bits
+    pub fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            HashFunction::Sha1 => Sha1::digest(bytes).to_vec(),
+            HashFunction::Sha256 => Sha256::digest(bytes).to_vec(),
+            HashFunction::Blake3 => blake3::hash(bytes).as_bytes().to_vec(),
+        }
+    }
+}
+
+impl FromStr for HashFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sha1" => Ok(HashFunction::Sha1),
+            "sha256" => Ok(HashFunction::Sha256),
+            "blake3" => Ok(HashFunction::Blake3),
+            _ => Err(format!("unknown hash function {:}", s)),
+        }
+    }
+}
+
+fn check_bits(bits: u32) -> Result<u32, String> {
+    if !SUPPORTED_BITS.contains(&bits) {
+        return Err(format!(
+            "unsupported identifier width {:}, expected one of {:?}",
+            bits, SUPPORTED_BITS
+        ));
     }
+    Ok(bits)
 }
 
-pub trait HashIdentifier<T> {
-    fn hash_id(&self) -> T;
+// parses the width of the identifiers given as command line argument
+pub fn parse_bits(s: &str) -> Result<u32, String> {
+    check_bits(s.parse::<u32>().map_err(|err| err.to_string())?)
+}
+
+// width and hash function of a ring, all nodes of a ring have to use the same
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct IdSpace {
+    pub bits: u32,
+    pub hash: HashFunction,
+}
+
+impl IdSpace {
+    pub fn new(bits: u32, hash: HashFunction) -> Result<Self, String> {
+        check_bits(bits).map(|bits| IdSpace { bits, hash })
+    }
+}
+
+impl Default for IdSpace {
+    fn default() -> Self {
+        IdSpace {
+            bits: 64,
+            hash: HashFunction::Sha256,
+        }
+    }
+}
+
+impl IdentifierHash for IdSpace {
+    fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    fn digest(&self, bytes: &[u8]) -> Vec<u8> {
+        self.hash.digest(bytes)
+    }
+}
+
+pub trait HashIdentifier {
+    // bytes the identifier is derived from
+    fn id_bytes(&self) -> Vec<u8>;
+
+    fn hash_id<H: IdentifierHash + ?Sized>(&self, hash: &H) -> Identifier {
+        hash.identifier(&self.id_bytes())
+    }
 }
 
-impl HashIdentifier<Identifier> for SocketAddrV4 {
-    fn hash_id(&self) -> Identifier {
+impl HashIdentifier for SocketAddrV4 {
+    fn id_bytes(&self) -> Vec<u8> {
         let mut bytes = [0u8; 6];
         bytes[..4].copy_from_slice(&self.ip().octets());
         bytes[4..].copy_from_slice(&self.port().to_le_bytes());
-        Identifier::from(bytes.as_ref())
+        bytes.to_vec()
     }
 }
 
-impl HashIdentifier<Identifier> for SocketAddrV6 {
-    fn hash_id(&self) -> Identifier {
+impl HashIdentifier for SocketAddrV6 {
+    fn id_bytes(&self) -> Vec<u8> {
         let mut bytes = [0u8; 18];
         bytes[..16].copy_from_slice(&self.ip().octets());
         bytes[16..].copy_from_slice(&self.port().to_le_bytes());
-        Identifier::from(bytes.as_ref())
+        bytes.to_vec()
     }
 }
 
-impl HashIdentifier<Identifier> for SocketAddr {
-    fn hash_id(&self) -> Identifier {
+impl HashIdentifier for SocketAddr {
+    fn id_bytes(&self) -> Vec<u8> {
         match self {
-            SocketAddr::V4(addr) => addr.hash_id(),
-            SocketAddr::V6(addr) => addr.hash_id(),
+            SocketAddr::V4(addr) => addr.id_bytes(),
+            SocketAddr::V6(addr) => addr.id_bytes(),
         }
     }
 }
 
-impl HashIdentifier<Identifier> for String {
-    fn hash_id(&self) -> Identifier {
-        Identifier::from(self.as_bytes().as_ref())
+impl HashIdentifier for String {
+    fn id_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
     }
 }
@@ -0,0 +1,452 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::warn;
+
+// a stored value together with the version it was written with
+// deleted keys are kept as tombstones (without a value) so that old
+// copies of them are not brought back by replication or key migration
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record<Value> {
+    pub version: u64,
+    pub value: Option<Value>,
+}
+
+impl<Value> Record<Value> {
+    // whether this record should replace the given stored one
+    pub fn is_newer_than(&self, other: Option<&Record<Value>>) -> bool {
+        other.map(|r| self.version > r.version).unwrap_or(true)
+    }
+}
+
+// key-value store used by a node for its own keys and replicas
+pub trait Storage<Key, Value>: Send {
+    fn get(&self, key: &Key) -> Option<Value>;
+    fn insert(&mut self, key: Key, value: Value) -> io::Result<()>;
+    fn remove(&mut self, key: &Key) -> io::Result<Option<Value>>;
+    fn iter(&self) -> Box<dyn Iterator<Item = (&Key, &Value)> + '_>;
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // inserts or removes many keys at once, durable backends persist
+    // them together instead of one after the other
+    fn insert_all(&mut self, pairs: Vec<(Key, Value)>) -> io::Result<()> {
+        for (key, value) in pairs {
+            self.insert(key, value)?;
+        }
+        Ok(())
+    }
+
+    fn remove_all(&mut self, keys: &[Key]) -> io::Result<()> {
+        for key in keys {
+            self.remove(key)?;
+        }
+        Ok(())
+    }
+
+    // persists the current state, only needed by durable backends
+    fn snapshot(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<Key, Value> Storage<Key, Value> for HashMap<Key, Value>
+where
+    Key: Eq + Hash + Send,
+    Value: Clone + Send,
+{
+    fn get(&self, key: &Key) -> Option<Value> {
+        HashMap::get(self, key).cloned()
+    }
+
+    fn insert(&mut self, key: Key, value: Value) -> io::Result<()> {
+        HashMap::insert(self, key, value);
+        Ok(())
+    }
+
+    fn remove(&mut self, key: &Key) -> io::Result<Option<Value>> {
+        Ok(HashMap::remove(self, key))
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&Key, &Value)> + '_> {
+        Box::new(HashMap::iter(self))
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+// a single entry of the write-ahead log
+#[derive(Serialize, Deserialize)]
+enum LogRecord<Value> {
+    Insert(String, Value),
+    Remove(String),
+}
+
+const SNAPSHOT_FILE: &str = "snapshot.cbor";
+const WAL_FILE: &str = "wal.log";
+
+// storage that keeps all pairs in memory and persists every change to an
+// append-only write-ahead log in its directory
+// a snapshot replaces the log with a full copy of the store
+// both are written in cbor, so binary values are stored as they are, every
+// record of the log is prefixed with its length as a big-endian u32
+pub struct DiskStorage<Key, Value> {
+    dir: PathBuf,
+    store: HashMap<Key, Value>,
+    wal: File,
+    // number of records in the log since the last snapshot
+    wal_records: usize,
+}
+
+impl<Key, Value> DiskStorage<Key, Value>
+where
+    Key: Eq + Hash + FromStr + ToString,
+    Value: Clone + Serialize + DeserializeOwned,
+    <Key as FromStr>::Err: fmt::Debug,
+{
+    // opens the storage in the given directory, restoring the last
+    // snapshot and replaying the write-ahead log
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut store = HashMap::new();
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        if snapshot_path.exists() {
+            let file = File::open(&snapshot_path)?;
+            let snapshot: HashMap<String, Value> =
+                serde_cbor::from_reader(BufReader::new(file)).map_err(invalid_data)?;
+            for (k, v) in snapshot {
+                store.insert(parse(&k)?, v);
+            }
+        }
+
+        let wal_path = dir.join(WAL_FILE);
+        let mut wal_records = 0;
+        // length of the log up to the end of the last complete record
+        let mut wal_len = 0;
+        if wal_path.exists() {
+            let mut reader = BufReader::new(File::open(&wal_path)?);
+            // the last record may be incomplete if the process died while writing it
+            while let Some(bytes) = read_record(&mut reader)? {
+                let record: LogRecord<Value> = match serde_cbor::from_slice(&bytes) {
+                    Ok(record) => record,
+                    // only the last record can be broken by a crash, records
+                    // after a broken one must not be cut off with it
+                    Err(err) if reader.fill_buf()?.is_empty() => {
+                        warn!(path = ?wal_path, error = %err, "last record of the log is broken");
+                        break;
+                    }
+                    Err(err) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "broken record at byte {:} of {:?}: {:}",
+                                wal_len, wal_path, err
+                            ),
+                        ))
+                    }
+                };
+                match record {
+                    LogRecord::Insert(k, v) => {
+                        store.insert(parse(&k)?, v);
+                    }
+                    LogRecord::Remove(k) => {
+                        store.remove(&parse::<Key>(&k)?);
+                    }
+                }
+                wal_records += 1;
+                wal_len += 4 + bytes.len() as u64;
+            }
+        }
+
+        let wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&wal_path)?;
+        // cut off what follows the last complete record, new records would
+        // otherwise be appended after it and never be replayed
+        if wal.metadata()?.len() > wal_len {
+            warn!(path = ?wal_path, len = wal_len, "cutting off the incomplete end of the log");
+            wal.set_len(wal_len)?;
+            wal.sync_all()?;
+        }
+        Ok(DiskStorage {
+            dir,
+            store,
+            wal,
+            wal_records,
+        })
+    }
+
+    // writes the records to the log with a single sync
+    fn append(&mut self, records: Vec<LogRecord<Value>>) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut buf = Vec::new();
+        for record in records.iter() {
+            let bytes = serde_cbor::to_vec(record).map_err(invalid_data)?;
+            buf.extend((bytes.len() as u32).to_be_bytes());
+            buf.extend(bytes);
+        }
+        self.wal.write_all(&buf)?;
+        self.wal.sync_data()?;
+        self.wal_records += records.len();
+        Ok(())
+    }
+}
+
+// reads the next length-prefixed record of the log, None at the end of
+// the log or if the record is cut off
+fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let len = u32::from_be_bytes(len) as u64;
+    // the buffer only grows as far as the file goes
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        return Ok(None);
+    }
+    Ok(Some(bytes))
+}
+
+fn invalid_data(err: serde_cbor::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn parse<T>(s: &str) -> io::Result<T>
+where
+    T: FromStr,
+    <T as FromStr>::Err: fmt::Debug,
+{
+    T::from_str(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))
+}
+
+impl<Key, Value> Storage<Key, Value> for DiskStorage<Key, Value>
+where
+    Key: Eq + Hash + FromStr + ToString + Send,
+    Value: Clone + Serialize + DeserializeOwned + Send,
+    <Key as FromStr>::Err: fmt::Debug,
+{
+    fn get(&self, key: &Key) -> Option<Value> {
+        self.store.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: Key, value: Value) -> io::Result<()> {
+        self.insert_all(vec![(key, value)])
+    }
+
+    fn remove(&mut self, key: &Key) -> io::Result<Option<Value>> {
+        if !self.store.contains_key(key) {
+            return Ok(None);
+        }
+        self.append(vec![LogRecord::Remove(key.to_string())])?;
+        Ok(self.store.remove(key))
+    }
+
+    fn insert_all(&mut self, pairs: Vec<(Key, Value)>) -> io::Result<()> {
+        let records = pairs
+            .iter()
+            .map(|(k, v)| LogRecord::Insert(k.to_string(), v.clone()))
+            .collect();
+        self.append(records)?;
+        self.store.extend(pairs);
+        Ok(())
+    }
+
+    fn remove_all(&mut self, keys: &[Key]) -> io::Result<()> {
+        let keys: Vec<&Key> = keys.iter().filter(|k| self.store.contains_key(k)).collect();
+        let records = keys
+            .iter()
+            .map(|k| LogRecord::Remove(k.to_string()))
+            .collect();
+        self.append(records)?;
+        for key in keys {
+            self.store.remove(key);
+        }
+        Ok(())
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (&Key, &Value)> + '_> {
+        Box::new(self.store.iter())
+    }
+
+    fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    fn snapshot(&mut self) -> io::Result<()> {
+        if self.wal_records == 0 {
+            return Ok(());
+        }
+        let snapshot: HashMap<String, &Value> =
+            self.store.iter().map(|(k, v)| (k.to_string(), v)).collect();
+
+        // write to a temporary file first so a crash never leaves a broken snapshot
+        let tmp_path = self.dir.join(format!("{:}.tmp", SNAPSHOT_FILE));
+        let mut tmp = File::create(&tmp_path)?;
+        serde_cbor::to_writer(&mut tmp, &snapshot).map_err(invalid_data)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE))?;
+
+        // all records are contained in the snapshot now
+        self.wal.set_len(0)?;
+        self.wal.sync_all()?;
+        self.wal_records = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("accord-storage-{:}-{:}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn open(dir: &Path) -> DiskStorage<String, u32> {
+        DiskStorage::open(dir).unwrap()
+    }
+
+    #[test]
+    fn replays_the_log() {
+        let dir = temp_dir("replay");
+        let mut storage = open(&dir);
+        storage.insert("a".to_string(), 1).unwrap();
+        storage.insert("b".to_string(), 2).unwrap();
+        storage.insert("a".to_string(), 3).unwrap();
+        storage.remove(&"b".to_string()).unwrap();
+        drop(storage);
+
+        let storage = open(&dir);
+        assert_eq!(storage.get(&"a".to_string()), Some(3));
+        assert_eq!(storage.get(&"b".to_string()), None);
+        assert_eq!(storage.len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replays_the_log_on_top_of_the_snapshot() {
+        let dir = temp_dir("snapshot");
+        let mut storage = open(&dir);
+        storage.insert("a".to_string(), 1).unwrap();
+        storage.insert("b".to_string(), 2).unwrap();
+        storage.snapshot().unwrap();
+        storage.insert("c".to_string(), 3).unwrap();
+        storage.remove(&"a".to_string()).unwrap();
+        drop(storage);
+
+        let storage = open(&dir);
+        assert_eq!(storage.get(&"a".to_string()), None);
+        assert_eq!(storage.get(&"b".to_string()), Some(2));
+        assert_eq!(storage.get(&"c".to_string()), Some(3));
+        assert_eq!(storage.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn writes_after_a_torn_record_survive_a_reopen() {
+        let dir = temp_dir("torn");
+        let mut storage = open(&dir);
+        storage.insert("a".to_string(), 1).unwrap();
+        drop(storage);
+        // the process died in the middle of writing the second record
+        let mut wal = OpenOptions::new()
+            .append(true)
+            .open(dir.join(WAL_FILE))
+            .unwrap();
+        let mut torn = 100u32.to_be_bytes().to_vec();
+        torn.extend(serde_cbor::to_vec(&"b").unwrap());
+        wal.write_all(&torn).unwrap();
+        drop(wal);
+
+        let mut storage = open(&dir);
+        assert_eq!(storage.get(&"b".to_string()), None);
+        storage.insert("c".to_string(), 3).unwrap();
+        drop(storage);
+
+        let storage = open(&dir);
+        assert_eq!(storage.get(&"a".to_string()), Some(1));
+        assert_eq!(storage.get(&"c".to_string()), Some(3));
+        assert_eq!(storage.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replays_batches() {
+        let dir = temp_dir("batch");
+        let mut storage = open(&dir);
+        let pairs = (0..10).map(|i| (i.to_string(), i)).collect();
+        storage.insert_all(pairs).unwrap();
+        let removed: Vec<String> = (0..5).map(|i| i.to_string()).collect();
+        storage.remove_all(&removed).unwrap();
+        drop(storage);
+
+        let storage = open(&dir);
+        assert_eq!(storage.len(), 5);
+        assert_eq!(storage.get(&"7".to_string()), Some(7));
+        assert_eq!(storage.get(&"2".to_string()), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_records_in_the_middle_of_the_log_are_an_error() {
+        let dir = temp_dir("broken");
+        let mut storage = open(&dir);
+        storage.insert("a".to_string(), 1).unwrap();
+        storage.insert("b".to_string(), 2).unwrap();
+        storage.insert("c".to_string(), 3).unwrap();
+        drop(storage);
+        // garbles the value of the first record
+        let path = dir.join(WAL_FILE);
+        let mut bytes = fs::read(&path).unwrap();
+        let len = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        bytes[3 + len] = 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let err = DiskStorage::<String, u32>::open(&dir).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        // nothing was cut off
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn binary_values_are_stored_as_they_are() {
+        let dir = temp_dir("binary");
+        let value = serde_bytes::ByteBuf::from(vec![0xff; 1000]);
+        let mut storage = DiskStorage::open(&dir).unwrap();
+        storage.insert("a".to_string(), value.clone()).unwrap();
+        assert!(fs::metadata(dir.join(WAL_FILE)).unwrap().len() < 1100);
+        storage.snapshot().unwrap();
+        assert!(fs::metadata(dir.join(SNAPSHOT_FILE)).unwrap().len() < 1100);
+        storage.insert("b".to_string(), value.clone()).unwrap();
+        drop(storage);
+
+        let storage: DiskStorage<String, serde_bytes::ByteBuf> = DiskStorage::open(&dir).unwrap();
+        assert_eq!(storage.get(&"a".to_string()), Some(value.clone()));
+        assert_eq!(storage.get(&"b".to_string()), Some(value));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
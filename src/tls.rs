@@ -0,0 +1,91 @@
+use rustls_pemfile::{certs, private_key};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+// certificates of a node, all of them signed by the ca that every node of
+// the ring trusts
+// nodes are addressed by ip, so the certificates need the ip addresses of
+// the node as subject alternative names
+#[derive(Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    pub ca_path: PathBuf,
+    // accepts chord connections from peers with a certificate signed by the ca
+    pub acceptor: TlsAcceptor,
+    // opens connections to other nodes, authenticated with the own certificate
+    pub connector: TlsConnector,
+}
+
+impl TlsConfig {
+    pub fn load(cert_path: &Path, key_path: &Path, ca_path: &Path) -> io::Result<Self> {
+        let certs = read_certs(cert_path)?;
+        let key = read_key(key_path)?;
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(ca_path)? {
+            roots.add(cert).map_err(invalid_data)?;
+        }
+        let roots = Arc::new(roots);
+
+        let verifier = WebPkiClientVerifier::builder(roots.clone())
+            .build()
+            .map_err(invalid_data)?;
+        let server = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs.clone(), key.clone_key())
+            .map_err(invalid_data)?;
+        let client = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(certs, key)
+            .map_err(invalid_data)?;
+
+        Ok(TlsConfig {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            ca_path: ca_path.to_path_buf(),
+            acceptor: TlsAcceptor::from(Arc::new(server)),
+            connector: TlsConnector::from(Arc::new(client)),
+        })
+    }
+
+    // opens a tls connection to the node and checks that its certificate
+    // is valid for the ip address
+    pub async fn connect(
+        &self,
+        addr: SocketAddr,
+        stream: TcpStream,
+    ) -> io::Result<TlsStream<TcpStream>> {
+        let name = ServerName::from(addr.ip());
+        self.connector.connect(name, stream).await
+    }
+}
+
+fn read_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certs = certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(invalid_data(format!("no certificates in {:?}", path)));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    private_key(&mut reader)?.ok_or_else(|| invalid_data(format!("no private key in {:?}", path)))
+}
+
+fn invalid_data<E>(err: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
@@ -0,0 +1,170 @@
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::sleep;
+
+use crate::auth::ClusterKey;
+use crate::network::{ConnectionPool, Message, MessageError, NetworkConfig};
+use crate::node::Neighbor;
+use crate::routing::id::Identifier;
+use crate::tls::TlsConfig;
+
+// the way messages are delivered to other nodes
+pub trait Transport: Send + Sync {
+    // sends a message to the (virtual) node with the given id at the chord
+    // address and waits for its response
+    fn send(
+        &self,
+        msg: Message,
+        addr: SocketAddr,
+        receiver: Option<Identifier>,
+    ) -> BoxFuture<'_, Result<Option<Message>, MessageError>>;
+}
+
+// sends messages over tcp connections that are kept open between messages,
+// optionally encrypted with tls or signed with a cluster key
+pub struct TcpTransport {
+    pool: ConnectionPool,
+}
+
+impl TcpTransport {
+    // the sender is announced to the receivers of all messages
+    pub fn new(config: NetworkConfig, sender: Option<Neighbor>) -> Self {
+        TcpTransport::with_security(config, sender, None, None)
+    }
+
+    // connects to other nodes over tls, authenticated with the node certificate,
+    // and signs all frames with the cluster key
+    pub fn with_security(
+        config: NetworkConfig,
+        sender: Option<Neighbor>,
+        tls: Option<TlsConfig>,
+        key: Option<ClusterKey>,
+    ) -> Self {
+        TcpTransport {
+            pool: ConnectionPool::new(config, sender, tls, key),
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(
+        &self,
+        msg: Message,
+        addr: SocketAddr,
+        receiver: Option<Identifier>,
+    ) -> BoxFuture<'_, Result<Option<Message>, MessageError>> {
+        Box::pin(self.pool.send_message(msg, addr, receiver))
+    }
+}
+
+type Request = (
+    Message,
+    Option<Identifier>,
+    oneshot::Sender<Option<Message>>,
+);
+
+struct MemoryState {
+    nodes: HashMap<SocketAddr, mpsc::UnboundedSender<Request>>,
+    // state of the xorshift generator used for message latencies
+    rng: u64,
+    max_latency: Duration,
+}
+
+impl MemoryState {
+    fn next_latency(&mut self) -> Duration {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let max = self.max_latency.as_micros() as u64;
+        if max == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_micros(self.rng % (max + 1))
+        }
+    }
+}
+
+// a network of nodes within a single process that exchange messages over
+// channels, every message is delayed by a random latency
+// all randomness comes from the seed, so with a current thread runtime and
+// paused time (tokio::time::pause) a simulation always runs the same way
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryNetwork {
+    pub fn new(seed: u64, max_latency: Duration) -> Self {
+        MemoryNetwork {
+            state: Arc::new(Mutex::new(MemoryState {
+                nodes: HashMap::new(),
+                // xorshift gets stuck at zero
+                rng: seed.max(1),
+                max_latency,
+            })),
+        }
+    }
+
+    // returns a transport that sends messages within this network
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        Arc::new(self.clone())
+    }
+
+    // delivers all messages sent to the address to the handler,
+    // every message is handled in its own task
+    pub fn listen<F, Fut>(&self, addr: SocketAddr, handler: F)
+    where
+        F: Fn(Message, Option<Identifier>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<Message>> + Send + 'static,
+    {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Request>();
+        self.state.lock().unwrap().nodes.insert(addr, tx);
+        let handler = Arc::new(handler);
+        tokio::spawn(async move {
+            while let Some((msg, receiver, reply)) = rx.recv().await {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let _ = reply.send(handler(msg, receiver).await);
+                });
+            }
+        });
+    }
+
+    // removes the node from the network, messages sent to it fail
+    // as if the process crashed
+    pub fn disconnect(&self, addr: SocketAddr) {
+        self.state.lock().unwrap().nodes.remove(&addr);
+    }
+}
+
+impl Transport for MemoryNetwork {
+    fn send(
+        &self,
+        msg: Message,
+        addr: SocketAddr,
+        receiver: Option<Identifier>,
+    ) -> BoxFuture<'_, Result<Option<Message>, MessageError>> {
+        Box::pin(async move {
+            let latency = self.state.lock().unwrap().next_latency();
+            sleep(latency).await;
+            let node = self.state.lock().unwrap().nodes.get(&addr).cloned();
+            let node = node.ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+
+            let (tx, rx) = oneshot::channel();
+            node.send((msg, receiver, tx))
+                .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+            // fails if the node stopped before it answered
+            let response = rx
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::ConnectionReset))?;
+            sleep(latency).await;
+            Ok(response)
+        })
+    }
+}
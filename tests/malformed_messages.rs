@@ -0,0 +1,402 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use accord::api::ChordNode;
+use accord::auth::{self, ClusterKey, Nonces};
+use accord::network::{
+    self, Envelope, Handshake, Message, MessageError, NetworkConfig, RemoteError, PROTOCOL_VERSION,
+    SUPPORTED_VERSIONS,
+};
+use accord::node::NodeConfig;
+use accord::routing::id::{HashFunction, IdSpace};
+use accord::transport::{TcpTransport, Transport};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+// xorshift, so that every run sends the same bytes
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn bytes(&mut self, len: usize) -> Vec<u8> {
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+async fn start_node() -> SocketAddr {
+    start_node_with(None, NetworkConfig::default())
+        .await
+        .address
+}
+
+// serves the node with the listener of the binary
+async fn start_node_with(key: Option<ClusterKey>, config: NetworkConfig) -> Arc<ChordNode> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let node = Arc::new(ChordNode::new(addr, addr, NodeConfig::default()));
+    let handler_node = node.clone();
+    tokio::spawn(network::listen_for_messages(
+        listener,
+        None,
+        key,
+        config,
+        node.metrics.clone(),
+        move |msg, receiver| {
+            let node = handler_node.clone();
+            async move { node.handle_request(msg, receiver).await }
+        },
+    ));
+    node
+}
+
+// sends the bytes and returns everything the node answers until it closes
+// the connection or stops answering
+async fn send_raw(addr: SocketAddr, bytes: &[u8]) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let _ = stream.write_all(bytes).await;
+    let _ = stream.shutdown().await;
+    let mut response = Vec::new();
+    let read = stream.read_to_end(&mut response);
+    let _ = tokio::time::timeout(Duration::from_millis(200), read).await;
+    response
+}
+
+fn framed(body: &[u8]) -> Vec<u8> {
+    let mut buf = (body.len() as u32).to_be_bytes().to_vec();
+    buf.extend_from_slice(body);
+    buf
+}
+
+fn hello(versions: Vec<u32>) -> Vec<u8> {
+    framed(&serde_cbor::to_vec(&Handshake::Hello(versions)).unwrap())
+}
+
+// the frames of a client that completes the handshake in the given space
+fn handshake(space: IdSpace, nonce: &[u8]) -> Vec<u8> {
+    let mut bytes = hello(vec![PROTOCOL_VERSION]);
+    let space = Handshake::Space(space);
+    bytes.extend_from_slice(&framed(&serde_cbor::to_vec(&space).unwrap()));
+    let nonce = Handshake::Nonce(serde_bytes::ByteBuf::from(nonce));
+    bytes.extend_from_slice(&framed(&serde_cbor::to_vec(&nonce).unwrap()));
+    bytes
+}
+
+// splits a response into the bodies of its frames
+fn frames(mut buf: &[u8]) -> Vec<&[u8]> {
+    let mut frames = Vec::new();
+    while buf.len() >= 4 {
+        let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        frames.push(&buf[4..4 + len]);
+        buf = &buf[4 + len..];
+    }
+    frames
+}
+
+// a connection that completed the handshake
+struct RawConnection {
+    stream: TcpStream,
+    nonces: Nonces,
+}
+
+impl RawConnection {
+    async fn open(addr: SocketAddr) -> Self {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let own = auth::new_nonce().unwrap();
+        let bytes = handshake(IdSpace::default(), &own);
+        stream.write_all(&bytes).await.unwrap();
+        let mut frames = Vec::new();
+        for _ in 0..3 {
+            let frame = network::read_frame(&mut stream).await.unwrap().unwrap();
+            frames.push(serde_cbor::from_slice(&frame).unwrap());
+        }
+        let peer = match &frames[..] {
+            [Handshake::Accept(PROTOCOL_VERSION), Handshake::Space(_), Handshake::Nonce(peer)] => {
+                peer.to_vec()
+            }
+            other => panic!("unexpected handshake {:?}", other),
+        };
+        RawConnection {
+            stream,
+            nonces: Nonces { own, peer },
+        }
+    }
+
+    // sends the frame and returns the response to it
+    async fn request(&mut self, frame: &[u8]) -> Envelope {
+        self.stream.write_all(frame).await.unwrap();
+        let response = network::read_frame(&mut self.stream)
+            .await
+            .unwrap()
+            .unwrap();
+        network::decode_envelope(&response).unwrap()
+    }
+}
+
+// sends the frame after a successful handshake and returns the response to it
+async fn request(addr: SocketAddr, frame: &[u8]) -> Envelope {
+    RawConnection::open(addr).await.request(frame).await
+}
+
+async fn assert_alive(addr: SocketAddr) {
+    let transport = TcpTransport::new(NetworkConfig::default(), None);
+    let response = transport.send(Message::Ping, addr, None).await.unwrap();
+    assert!(matches!(response, Some(Message::Pong)));
+}
+
+#[tokio::test]
+async fn listener_survives_random_bytes() {
+    let node = start_node_with(None, NetworkConfig::default()).await;
+    let addr = node.address;
+    let mut rng = Rng(0x5eed);
+
+    for _ in 0..200 {
+        let len = (rng.next() % 512) as usize;
+        let body = rng.bytes(len);
+        // unframed garbage, frames with a random body and truncated frames,
+        // before and after the handshake
+        let frame = framed(&body);
+        for bytes in [
+            body.clone(),
+            frame.clone(),
+            frame[..frame.len() / 2].to_vec(),
+        ] {
+            send_raw(addr, &bytes).await;
+            let mut after_hello = hello(vec![PROTOCOL_VERSION]);
+            after_hello.extend_from_slice(&bytes);
+            send_raw(addr, &after_hello).await;
+            let mut after_handshake = handshake(IdSpace::default(), &[0; auth::NONCE_LEN]);
+            after_handshake.extend_from_slice(&bytes);
+            send_raw(addr, &after_handshake).await;
+        }
+    }
+    assert_alive(addr).await;
+    assert_eq!(node.metrics.panics.get(), 0);
+}
+
+#[tokio::test]
+async fn malformed_and_unexpected_messages_get_error_responses() {
+    let addr = start_node().await;
+
+    // an envelope with a message that is no chord message
+    let envelope = Envelope {
+        version: PROTOCOL_VERSION,
+        id: 7,
+        sender: None,
+        receiver: None,
+        message: Some(serde_bytes::ByteBuf::from(
+            serde_cbor::to_vec(&"hello").unwrap(),
+        )),
+        mac: None,
+    };
+    let response = request(addr, &framed(&serde_cbor::to_vec(&envelope).unwrap())).await;
+    assert_eq!(response.id, 7);
+    assert!(matches!(
+        response.message().unwrap(),
+        Some(Message::Error(RemoteError::Malformed(_)))
+    ));
+
+    // a response sent as request
+    let envelope = Envelope::new(PROTOCOL_VERSION, 8, None, Some(&Message::Pong)).unwrap();
+    let response = request(addr, &framed(&serde_cbor::to_vec(&envelope).unwrap())).await;
+    assert_eq!(response.id, 8);
+    assert!(matches!(
+        response.message().unwrap(),
+        Some(Message::Error(RemoteError::Unexpected(_)))
+    ));
+
+    // a message with another version than the one agreed on
+    let envelope = Envelope::new(PROTOCOL_VERSION + 1, 9, None, Some(&Message::Ping)).unwrap();
+    let response = request(addr, &framed(&serde_cbor::to_vec(&envelope).unwrap())).await;
+    assert_eq!(response.id, 9);
+    assert!(matches!(
+        response.message().unwrap(),
+        Some(Message::Error(RemoteError::UnsupportedVersion(_, _)))
+    ));
+
+    // frames that are too large are rejected without reading them
+    let response = request(addr, &u32::MAX.to_be_bytes()).await;
+    assert!(matches!(
+        response.message().unwrap(),
+        Some(Message::Error(RemoteError::Malformed(_)))
+    ));
+
+    assert_alive(addr).await;
+}
+
+#[tokio::test]
+async fn incompatible_peers_are_rejected() {
+    let addr = start_node().await;
+    // peers that have no version in common with the node
+    for versions in [vec![PROTOCOL_VERSION + 1], vec![PROTOCOL_VERSION + 1, 0]] {
+        let response = send_raw(addr, &hello(versions)).await;
+        match serde_cbor::from_slice(frames(&response)[0]).unwrap() {
+            Handshake::Reject(versions) => assert_eq!(versions, SUPPORTED_VERSIONS),
+            other => panic!("unexpected handshake {:?}", other),
+        }
+    }
+
+    // a hello that is too large is not read
+    let mut large = (1024 * 1024u32).to_be_bytes().to_vec();
+    large.extend_from_slice(&[0; 1024]);
+    assert!(send_raw(addr, &large).await.is_empty());
+    assert_alive(addr).await;
+
+    // a peer that only talks a future version
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let peer = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let reject = Handshake::Reject(vec![PROTOCOL_VERSION + 1]);
+        let reply = framed(&serde_cbor::to_vec(&reject).unwrap());
+        stream.write_all(&reply).await.unwrap();
+    });
+    let transport = TcpTransport::new(NetworkConfig::default(), None);
+    match transport.send(Message::Ping, peer, None).await {
+        Err(MessageError::IncompatibleVersion(addr, versions)) => {
+            assert_eq!(addr, peer);
+            assert_eq!(versions, vec![PROTOCOL_VERSION + 1]);
+        }
+        other => panic!("expected version error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn silent_clients_are_disconnected() {
+    let config = NetworkConfig {
+        read_timeout: Duration::from_millis(100),
+        ..NetworkConfig::default()
+    };
+    let addr = start_node_with(None, config).await.address;
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut response = Vec::new();
+    let read = stream.read_to_end(&mut response);
+    let closed = tokio::time::timeout(Duration::from_secs(2), read).await;
+    assert!(matches!(closed, Ok(Ok(0))));
+}
+
+#[tokio::test]
+async fn peers_with_other_identifiers_are_rejected() {
+    let addr = start_node().await;
+    let space = IdSpace::new(160, HashFunction::Sha1).unwrap();
+    let mut bytes = handshake(space, &[0; auth::NONCE_LEN]);
+    bytes.extend_from_slice(&framed(
+        &serde_cbor::to_vec(
+            &Envelope::new(PROTOCOL_VERSION, 1, None, Some(&Message::Ping)).unwrap(),
+        )
+        .unwrap(),
+    ));
+    let response = send_raw(addr, &bytes).await;
+    // the node names its own space and answers no requests
+    let frames = frames(&response);
+    assert_eq!(frames.len(), 2);
+    match serde_cbor::from_slice(frames[1]).unwrap() {
+        Handshake::Space(theirs) => assert_eq!(theirs, IdSpace::default()),
+        other => panic!("unexpected handshake {:?}", other),
+    }
+
+    let config = NetworkConfig {
+        id_space: IdSpace::new(64, HashFunction::Blake3).unwrap(),
+        ..NetworkConfig::default()
+    };
+    let transport = TcpTransport::new(config, None);
+    match transport.send(Message::Ping, addr, None).await {
+        Err(MessageError::IncompatibleIdSpace(peer, theirs)) => {
+            assert_eq!(peer, addr);
+            assert_eq!(theirs, IdSpace::default());
+        }
+        other => panic!("expected identifier space error, got {:?}", other),
+    }
+    assert_alive(addr).await;
+}
+
+#[tokio::test]
+async fn unsigned_and_forged_frames_are_rejected() {
+    let key = ClusterKey::new(b"secret").unwrap();
+    let addr = start_node_with(Some(key.clone()), NetworkConfig::default())
+        .await
+        .address;
+    let is_rejected = |envelope: &Envelope| {
+        matches!(
+            envelope.message().unwrap(),
+            Some(Message::Error(RemoteError::Unauthenticated))
+        )
+    };
+
+    let mut envelope = Envelope::new(PROTOCOL_VERSION, 1, None, Some(&Message::Ping)).unwrap();
+    let unsigned = framed(&serde_cbor::to_vec(&envelope).unwrap());
+    assert!(is_rejected(&request(addr, &unsigned).await));
+
+    let mut connection = RawConnection::open(addr).await;
+    let nonces = connection.nonces.sent();
+    envelope.sign(&ClusterKey::new(b"guess").unwrap(), &nonces);
+    let wrong_key = framed(&serde_cbor::to_vec(&envelope).unwrap());
+    assert!(is_rejected(&connection.request(&wrong_key).await));
+
+    // a signed message that was changed afterwards
+    envelope.sign(&key, &nonces);
+    envelope.message = Some(serde_bytes::ByteBuf::from(
+        serde_cbor::to_vec(&Message::LeavePredecessor(None)).unwrap(),
+    ));
+    let forged = framed(&serde_cbor::to_vec(&envelope).unwrap());
+    assert!(is_rejected(&connection.request(&forged).await));
+
+    let mut envelope = Envelope::new(PROTOCOL_VERSION, 2, None, Some(&Message::Ping)).unwrap();
+    envelope.sign(&key, &nonces);
+    let signed = framed(&serde_cbor::to_vec(&envelope).unwrap());
+    let response = connection.request(&signed).await;
+    assert!(response.is_signed_by(&key, &connection.nonces.received()));
+    assert!(matches!(response.message().unwrap(), Some(Message::Pong)));
+
+    let config = NetworkConfig::default();
+    let guess = ClusterKey::new(b"guess").unwrap();
+    let transport = TcpTransport::with_security(config, None, None, Some(guess));
+    match transport.send(Message::Ping, addr, None).await {
+        Ok(Some(Message::Error(RemoteError::Unauthenticated))) => {}
+        other => panic!("expected authentication error, got {:?}", other),
+    }
+    let transport = TcpTransport::with_security(config, None, None, Some(key));
+    let response = transport.send(Message::Ping, addr, None).await.unwrap();
+    assert!(matches!(response, Some(Message::Pong)));
+}
+
+#[tokio::test]
+async fn replayed_frames_are_rejected() {
+    let key = ClusterKey::new(b"secret").unwrap();
+    let addr = start_node_with(Some(key.clone()), NetworkConfig::default())
+        .await
+        .address;
+
+    let mut first = RawConnection::open(addr).await;
+    let msg = Message::LeavePredecessor(None);
+    let mut envelope = Envelope::new(PROTOCOL_VERSION, 1, None, Some(&msg)).unwrap();
+    envelope.sign(&key, &first.nonces.sent());
+    let captured = framed(&serde_cbor::to_vec(&envelope).unwrap());
+    let response = first.request(&captured).await;
+    assert!(response.message().unwrap().is_none());
+
+    // the frame is only valid on the connection it was sent on
+    let mut second = RawConnection::open(addr).await;
+    let response = second.request(&captured).await;
+    assert!(matches!(
+        response.message().unwrap(),
+        Some(Message::Error(RemoteError::Unauthenticated))
+    ));
+
+    // and only in the direction it was sent in
+    let mut reflected = Envelope::new(PROTOCOL_VERSION, 2, None, Some(&Message::Ping)).unwrap();
+    reflected.sign(&key, &second.nonces.received());
+    let response = second
+        .request(&framed(&serde_cbor::to_vec(&reflected).unwrap()))
+        .await;
+    assert!(matches!(
+        response.message().unwrap(),
+        Some(Message::Error(RemoteError::Unauthenticated))
+    ));
+}
@@ -0,0 +1,817 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use accord::api::{Blob, ChordNode};
+use accord::events::{now_ms, Event, TimedEvent};
+use accord::network::NetworkConfig;
+use accord::node::{LookupMode, Neighbor, NodeConfig, VirtualNode};
+use accord::ring::Inconsistency;
+use accord::routing::id::{HashFunction, HashIdentifier, IdSpace, Identifier};
+use accord::transport::MemoryNetwork;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+fn addr(i: usize) -> SocketAddr {
+    format!("10.0.{:}.{:}:4000", i / 256, i % 256)
+        .parse()
+        .unwrap()
+}
+
+fn blob(s: &str) -> Blob {
+    Blob {
+        content_type: None,
+        data: s.as_bytes().to_vec(),
+    }
+}
+
+fn spawn_node(net: &MemoryNetwork, i: usize, config: NodeConfig) -> Arc<ChordNode> {
+    let node = Arc::new(ChordNode::with_transport(
+        addr(i),
+        addr(i),
+        config,
+        net.transport(),
+        Box::new(HashMap::new()),
+        Box::new(HashMap::new()),
+    ));
+    connect(net, &node);
+    node
+}
+
+// delivers the messages sent to the address of the node to it
+fn connect(net: &MemoryNetwork, node: &Arc<ChordNode>) {
+    let handler_node = node.clone();
+    net.listen(node.address, move |msg, receiver| {
+        let node = handler_node.clone();
+        async move { node.handle_message(msg, receiver).await.unwrap_or(None) }
+    });
+}
+
+// sum of a metric over all nodes, including its labels if it has any,
+// counters that were never incremented are missing and count as zero
+async fn metric_sum(nodes: &[Arc<ChordNode>], name: &str) -> f64 {
+    let mut sum = 0.0;
+    for node in nodes {
+        let text = node.render_metrics().await;
+        if let Some(line) = text.lines().find(|l| l.split(' ').next() == Some(name)) {
+            sum += line[name.len() + 1..].parse::<f64>().unwrap();
+        }
+    }
+    sum
+}
+
+// creates n nodes that all join through the first one
+async fn create_ring(net: &MemoryNetwork, n: usize, config: NodeConfig) -> Vec<Arc<ChordNode>> {
+    let mut nodes = vec![spawn_node(net, 0, config)];
+    for i in 1..n {
+        let node = spawn_node(net, i, config);
+        node.join(nodes[0].address).await.unwrap();
+        nodes.push(node);
+        stabilize(&nodes, 1).await;
+    }
+    // a node that joined with a wrong successor moves one node
+    // closer to its real successor per round
+    stabilize(&nodes, n * config.vnodes).await;
+    nodes
+}
+
+// runs the periodic maintenance of all nodes the given number of times
+async fn stabilize(nodes: &[Arc<ChordNode>], rounds: usize) {
+    for _ in 0..rounds {
+        for node in nodes {
+            let _ = node.stabilize().await;
+            let _ = node.check_successors().await;
+            node.check_predecessor().await;
+            for _ in 0..8 {
+                let _ = node.fix_fingers().await;
+            }
+        }
+    }
+}
+
+// checks that the virtual nodes of all nodes form a single ordered ring
+async fn assert_ring(nodes: &[Arc<ChordNode>]) {
+    let vnodes: Vec<&VirtualNode> = nodes.iter().flat_map(|n| n.vnodes.iter()).collect();
+    let mut ids: Vec<_> = vnodes.iter().map(|v| v.id).collect();
+    ids.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    for vnode in vnodes {
+        let pos = ids.iter().position(|id| *id == vnode.id).unwrap();
+        let successor = vnode.successor.lock().await.id;
+        let predecessor = vnode.predecessor.lock().await.map(|p| p.id);
+        assert_eq!(successor, ids[(pos + 1) % ids.len()]);
+        assert_eq!(predecessor, Some(ids[(pos + ids.len() - 1) % ids.len()]));
+    }
+}
+
+// the events that were emitted since the last call
+fn drain(events: &mut broadcast::Receiver<TimedEvent>) -> Vec<Event> {
+    let mut drained = Vec::new();
+    while let Ok(timed) = events.try_recv() {
+        drained.push(timed.event);
+    }
+    drained
+}
+
+// the first virtual node at or after the id
+fn expected_owner(nodes: &[Arc<ChordNode>], id: Identifier) -> Identifier {
+    let mut ids: Vec<_> = nodes
+        .iter()
+        .flat_map(|n| n.vnodes.iter().map(|v| v.id))
+        .collect();
+    ids.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    *ids.iter().find(|v| **v >= id).unwrap_or(&ids[0])
+}
+
+#[tokio::test(start_paused = true)]
+async fn join_forms_consistent_ring() {
+    let net = MemoryNetwork::new(1, Duration::from_millis(20));
+    let nodes = create_ring(&net, 32, NodeConfig::default()).await;
+    assert_ring(&nodes).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn fingers_converge_to_true_successors() {
+    let net = MemoryNetwork::new(12, Duration::from_millis(20));
+    let nodes = create_ring(&net, 16, NodeConfig::default()).await;
+    // every round fixes 8 fingers per node, enough to refresh all 64
+    stabilize(&nodes, 8).await;
+    for node in nodes.iter() {
+        let vnode = &node.vnodes[0];
+        let fingers = vnode.fingers.lock().await;
+        for (i, finger) in fingers.iter().enumerate() {
+            let expected = expected_owner(&nodes, vnode.id.offset(i));
+            assert_eq!(finger.map(|f| f.id), Some(expected), "finger {:}", i);
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn keys_survive_crashes_with_replication() {
+    let net = MemoryNetwork::new(2, Duration::from_millis(20));
+    let config = NodeConfig {
+        replication_factor: 3,
+        ..NodeConfig::default()
+    };
+    let mut nodes = create_ring(&net, 16, config).await;
+    for k in 0..100 {
+        let node = &nodes[k % nodes.len()];
+        node.put(format!("key{:}", k), blob(&format!("value{:}", k)))
+            .await
+            .unwrap();
+    }
+
+    for i in [3, 9] {
+        net.disconnect(nodes[i].address);
+    }
+    nodes.retain(|n| n.address != addr(3) && n.address != addr(9));
+    stabilize(&nodes, 5).await;
+    assert_ring(&nodes).await;
+
+    for k in 0..100 {
+        let value = nodes[0].lookup(format!("key{:}", k)).await.unwrap();
+        assert_eq!(value, Some(blob(&format!("value{:}", k))));
+    }
+}
+
+// puts the keys and deletes every second one
+async fn put_and_delete_evens(node: &ChordNode, n: usize) {
+    for k in 0..n {
+        node.put(format!("key{:}", k), blob(&format!("value{:}", k)))
+            .await
+            .unwrap();
+    }
+    for k in (0..n).step_by(2) {
+        assert!(node.delete(format!("key{:}", k)).await.unwrap());
+    }
+}
+
+async fn assert_evens_deleted(node: &ChordNode, n: usize) {
+    for k in 0..n {
+        let value = node.lookup(format!("key{:}", k)).await.unwrap();
+        let expected = if k % 2 == 0 {
+            None
+        } else {
+            Some(blob(&format!("value{:}", k)))
+        };
+        assert_eq!(value, expected, "key{:}", k);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn deleting_missing_keys_reports_it() {
+    let net = MemoryNetwork::new(17, Duration::from_millis(20));
+    let nodes = create_ring(&net, 4, NodeConfig::default()).await;
+    put_and_delete_evens(&nodes[0], 20).await;
+    for k in 0..20 {
+        // already deleted or never written
+        assert!(!nodes[1].delete(format!("key{:}", k * 2)).await.unwrap());
+        assert!(!nodes[2].delete(format!("other{:}", k)).await.unwrap());
+    }
+    assert_evens_deleted(&nodes[3], 20).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn deleted_keys_stay_deleted_after_crashes() {
+    let net = MemoryNetwork::new(18, Duration::from_millis(20));
+    let config = NodeConfig {
+        replication_factor: 2,
+        ..NodeConfig::default()
+    };
+    let mut nodes = create_ring(&net, 8, config).await;
+    put_and_delete_evens(&nodes[0], 100).await;
+
+    // the successors of the crashed owners promote their replicas,
+    // including the tombstones
+    for i in [2, 5] {
+        net.disconnect(nodes[i].address);
+    }
+    nodes.retain(|n| n.address != addr(2) && n.address != addr(5));
+    stabilize(&nodes, 5).await;
+    assert_ring(&nodes).await;
+    assert_evens_deleted(&nodes[0], 100).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn deleted_keys_stay_deleted_after_joins_and_leaves() {
+    let net = MemoryNetwork::new(19, Duration::from_millis(20));
+    let config = NodeConfig {
+        replication_factor: 2,
+        ..NodeConfig::default()
+    };
+    let mut nodes = create_ring(&net, 4, config).await;
+    put_and_delete_evens(&nodes[0], 100).await;
+
+    for i in 4..8 {
+        let node = spawn_node(&net, i, config);
+        node.join(nodes[0].address).await.unwrap();
+        nodes.push(node);
+        stabilize(&nodes, 1).await;
+    }
+    stabilize(&nodes, 8).await;
+    assert_ring(&nodes).await;
+    assert_evens_deleted(&nodes[0], 100).await;
+
+    for i in [1, 6] {
+        nodes[i].leave().await.unwrap();
+        net.disconnect(nodes[i].address);
+    }
+    nodes.retain(|n| n.address != addr(1) && n.address != addr(6));
+    stabilize(&nodes, 5).await;
+    assert_ring(&nodes).await;
+    assert_evens_deleted(&nodes[0], 100).await;
+    // only the keys with a value are counted
+    assert_eq!(metric_sum(&nodes, "accord_store_keys").await, 50.0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn former_replica_holders_drop_their_copies() {
+    let net = MemoryNetwork::new(13, Duration::from_millis(20));
+    let config = NodeConfig {
+        replication_factor: 2,
+        ..NodeConfig::default()
+    };
+    let mut nodes = create_ring(&net, 4, config).await;
+    for k in 0..100 {
+        nodes[0]
+            .put(format!("key{:}", k), blob(&format!("value{:}", k)))
+            .await
+            .unwrap();
+    }
+
+    // new nodes take over keys and become replica holders of the old ones
+    for i in 4..12 {
+        let node = spawn_node(&net, i, config);
+        node.join(nodes[0].address).await.unwrap();
+        nodes.push(node);
+        stabilize(&nodes, 1).await;
+    }
+    stabilize(&nodes, 12).await;
+    assert_ring(&nodes).await;
+
+    // every key has one primary and one copy left
+    assert_eq!(metric_sum(&nodes, "accord_replica_keys").await, 100.0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn failed_replica_writes_are_repeated() {
+    let net = MemoryNetwork::new(15, Duration::from_millis(20));
+    let config = NodeConfig {
+        replication_factor: 2,
+        ..NodeConfig::default()
+    };
+    let nodes = create_ring(&net, 4, config).await;
+
+    // the replicas of the keys of its predecessor are not written to the
+    // unreachable node, and its own keys are not written at all
+    net.disconnect(addr(1));
+    for k in 0..100 {
+        let _ = nodes[0]
+            .put(format!("key{:}", k), blob(&format!("value{:}", k)))
+            .await;
+    }
+    connect(&net, &nodes[1]);
+    stabilize(&nodes, 3).await;
+    assert_ring(&nodes).await;
+
+    let primaries = metric_sum(&nodes, "accord_store_keys").await;
+    assert!(primaries > 50.0, "{}", primaries);
+    assert_eq!(metric_sum(&nodes, "accord_replica_keys").await, primaries);
+}
+
+#[tokio::test(start_paused = true)]
+async fn joining_nodes_get_their_keys_from_their_successor() {
+    let net = MemoryNetwork::new(16, Duration::from_millis(20));
+    let mut nodes = create_ring(&net, 4, NodeConfig::default()).await;
+    for k in 0..200 {
+        nodes[0]
+            .put(format!("key{:}", k), blob(&format!("value{:}", k)))
+            .await
+            .unwrap();
+    }
+
+    let joining = spawn_node(&net, 4, NodeConfig::default());
+    joining.join(addr(0)).await.unwrap();
+    // the new node becomes the predecessor of its successor, which hands
+    // off the keys in its next run without looking up their owner
+    joining.stabilize().await.unwrap();
+    let successor_addr = joining.successors().await[0].addr;
+    let successor = nodes.iter().find(|n| n.address == successor_addr).unwrap();
+    let lookups = r#"accord_messages_sent_total{message="Lookup"}"#;
+    let before = metric_sum(std::slice::from_ref(successor), lookups).await;
+    successor.stabilize().await.unwrap();
+    assert_eq!(
+        metric_sum(std::slice::from_ref(successor), lookups).await,
+        before
+    );
+    assert!(metric_sum(std::slice::from_ref(&joining), "accord_store_keys").await > 0.0);
+
+    nodes.push(joining);
+    stabilize(&nodes, 4).await;
+    assert_ring(&nodes).await;
+    assert_eq!(metric_sum(&nodes, "accord_store_keys").await, 200.0);
+    for k in 0..200 {
+        let value = nodes[1].lookup(format!("key{:}", k)).await.unwrap();
+        assert_eq!(value, Some(blob(&format!("value{:}", k))));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn binary_values_keep_their_content_type() {
+    let net = MemoryNetwork::new(20, Duration::from_millis(20));
+    let config = NodeConfig {
+        replication_factor: 2,
+        ..NodeConfig::default()
+    };
+    let nodes = create_ring(&net, 4, config).await;
+    let space = config.network.id_space;
+    let value = Blob {
+        content_type: Some("image/png".to_string()),
+        data: vec![0xff, 0x00, 0x89],
+    };
+    // keys owned by neither the node that writes nor the one that reads them
+    let keys: Vec<String> = (0..50)
+        .map(|k| format!("key{:}", k))
+        .filter(|k| {
+            let owner = expected_owner(&nodes, k.hash_id(&space));
+            owner != nodes[0].id && owner != nodes[1].id
+        })
+        .collect();
+    assert!(!keys.is_empty());
+    for k in keys.iter() {
+        nodes[0].put(k.clone(), value.clone()).await.unwrap();
+    }
+    for k in keys.iter() {
+        assert_eq!(
+            nodes[1].lookup(k.clone()).await.unwrap(),
+            Some(value.clone())
+        );
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn leaving_nodes_hand_over_keys() {
+    let net = MemoryNetwork::new(3, Duration::from_millis(20));
+    let mut nodes = create_ring(&net, 8, NodeConfig::default()).await;
+    for k in 0..50 {
+        nodes[0]
+            .put(format!("key{:}", k), blob(&format!("value{:}", k)))
+            .await
+            .unwrap();
+    }
+
+    for i in [2, 5] {
+        nodes[i].leave().await.unwrap();
+        net.disconnect(nodes[i].address);
+    }
+    nodes.retain(|n| n.address != addr(2) && n.address != addr(5));
+    stabilize(&nodes, 5).await;
+    assert_ring(&nodes).await;
+
+    for k in 0..50 {
+        let value = nodes[1].lookup(format!("key{:}", k)).await.unwrap();
+        assert_eq!(value, Some(blob(&format!("value{:}", k))));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn same_seed_gives_same_run() {
+    let mut durations = Vec::new();
+    for _ in 0..2 {
+        let start = Instant::now();
+        let net = MemoryNetwork::new(42, Duration::from_millis(50));
+        create_ring(&net, 16, NodeConfig::default()).await;
+        durations.push(start.elapsed());
+    }
+    assert_eq!(durations[0], durations[1]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn virtual_nodes_form_one_ring() {
+    let net = MemoryNetwork::new(4, Duration::from_millis(20));
+    let config = NodeConfig {
+        vnodes: 4,
+        replication_factor: 2,
+        ..NodeConfig::default()
+    };
+    let mut nodes = create_ring(&net, 8, config).await;
+    assert_ring(&nodes).await;
+    for k in 0..100 {
+        let node = &nodes[k % nodes.len()];
+        node.put(format!("key{:}", k), blob(&format!("value{:}", k)))
+            .await
+            .unwrap();
+    }
+
+    // with two copies of each key the crash may only happen after the
+    // replicas that the leaving node held have been written again
+    nodes[2].leave().await.unwrap();
+    net.disconnect(addr(2));
+    nodes.retain(|n| n.address != addr(2));
+    stabilize(&nodes, 2).await;
+    net.disconnect(addr(5));
+    nodes.retain(|n| n.address != addr(5));
+    stabilize(&nodes, 5).await;
+    assert_ring(&nodes).await;
+
+    for k in 0..100 {
+        let node = &nodes[k % nodes.len()];
+        let value = node.lookup(format!("key{:}", k)).await.unwrap();
+        assert_eq!(value, Some(blob(&format!("value{:}", k))));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn leaving_nodes_hand_over_keys_of_all_virtual_nodes() {
+    let net = MemoryNetwork::new(14, Duration::from_millis(20));
+    let config = NodeConfig {
+        vnodes: 4,
+        replication_factor: 1,
+        ..NodeConfig::default()
+    };
+    let mut nodes = create_ring(&net, 6, config).await;
+    for k in 0..100 {
+        nodes[0]
+            .put(format!("key{:}", k), blob(&format!("value{:}", k)))
+            .await
+            .unwrap();
+    }
+
+    // without replicas every key has to be handed over, including those of
+    // virtual nodes that are followed by another virtual node of the same node
+    while nodes.len() > 2 {
+        let node = nodes.pop().unwrap();
+        node.leave().await.unwrap();
+        net.disconnect(node.address);
+        stabilize(&nodes, 5).await;
+    }
+    assert_ring(&nodes).await;
+
+    for k in 0..100 {
+        let value = nodes[0].lookup(format!("key{:}", k)).await.unwrap();
+        assert_eq!(value, Some(blob(&format!("value{:}", k))));
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn wide_identifiers_form_ring() {
+    for (bits, hash) in [
+        (128, HashFunction::Blake3),
+        (160, HashFunction::Sha1),
+        (160, HashFunction::Sha256),
+    ] {
+        let net = MemoryNetwork::new(5, Duration::from_millis(20));
+        let config = NodeConfig {
+            network: NetworkConfig {
+                id_space: IdSpace::new(bits, hash).unwrap(),
+                ..NetworkConfig::default()
+            },
+            ..NodeConfig::default()
+        };
+        let nodes = create_ring(&net, 16, config).await;
+        assert_ring(&nodes).await;
+        assert!(nodes.iter().all(|n| n.id.bits() == bits));
+        for k in 0..50 {
+            nodes[k % nodes.len()]
+                .put(format!("key{:}", k), blob(&format!("value{:}", k)))
+                .await
+                .unwrap();
+        }
+        for k in 0..50 {
+            let value = nodes[0].lookup(format!("key{:}", k)).await.unwrap();
+            assert_eq!(value, Some(blob(&format!("value{:}", k))));
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn iterative_lookups_skip_unresponsive_hops() {
+    let net = MemoryNetwork::new(6, Duration::from_millis(20));
+    let nodes = create_ring(&net, 16, NodeConfig::default()).await;
+    let space = IdSpace::default();
+    let ids: Vec<Identifier> = (0..50)
+        .map(|k| format!("key{:}", k).hash_id(&space))
+        .collect();
+    for id in ids.iter() {
+        let expected = expected_owner(&nodes, *id);
+        for mode in [LookupMode::Recursive, LookupMode::Iterative] {
+            let owner = nodes[0].find_owner(*id, mode).await.unwrap();
+            assert_eq!(owner.id, expected);
+        }
+    }
+
+    // a node that accepts requests but never answers them
+    let hanging = nodes[7].address;
+    net.listen(hanging, |_, _| std::future::pending());
+    for id in ids.iter() {
+        let owner = nodes[0]
+            .find_owner(*id, LookupMode::Iterative)
+            .await
+            .unwrap();
+        assert_eq!(owner.id, expected_owner(&nodes, *id));
+    }
+
+    // values of keys the hanging node is not responsible for stay available
+    let key = (0..)
+        .map(|k| format!("key{:}", k))
+        .find(|k| {
+            let owner = expected_owner(&nodes, k.hash_id(&space));
+            nodes[7].vnodes.iter().all(|v| v.id != owner)
+        })
+        .unwrap();
+    nodes[3]
+        .put_with(key.clone(), blob("value"), LookupMode::Iterative)
+        .await
+        .unwrap();
+    let value = nodes[12]
+        .lookup_with(key, LookupMode::Iterative)
+        .await
+        .unwrap();
+    assert_eq!(value, Some(blob("value")));
+}
+
+#[tokio::test(start_paused = true)]
+async fn traces_show_path_to_owner() {
+    let net = MemoryNetwork::new(7, Duration::from_millis(20));
+    let nodes = create_ring(&net, 16, NodeConfig::default()).await;
+    let space = IdSpace::default();
+    for mode in [LookupMode::Recursive, LookupMode::Iterative] {
+        for k in 0..20 {
+            let id = format!("key{:}", k).hash_id(&space);
+            let trace = nodes[0].trace(id, mode).await;
+            assert_eq!(trace.owner.unwrap().id, expected_owner(&nodes, id));
+            assert_eq!(trace.hops[0].node.addr, nodes[0].address);
+            // every hop gets closer to the id
+            for pair in trace.hops.windows(2) {
+                assert!(pair[1].node.id.is_between(pair[0].node.id, id));
+                assert!(pair[1].error.is_none());
+                assert!(pair[1].latency > Duration::ZERO);
+            }
+        }
+    }
+
+    // a lookup through a crashed node shows the failed hop
+    let mut traced = None;
+    for k in 0.. {
+        let id = format!("key{:}", k).hash_id(&space);
+        let trace = nodes[0].trace(id, LookupMode::Iterative).await;
+        if trace.hops.len() > 1 {
+            traced = Some((id, trace.hops.last().unwrap().node.addr));
+            break;
+        }
+    }
+    let (id, crashed) = traced.unwrap();
+    net.disconnect(crashed);
+    let trace = nodes[0].trace(id, LookupMode::Iterative).await;
+    assert!(trace
+        .hops
+        .iter()
+        .any(|hop| hop.node.addr == crashed && hop.error.is_some()));
+}
+
+#[tokio::test(start_paused = true)]
+async fn metrics_count_ring_activity() {
+    let net = MemoryNetwork::new(8, Duration::from_millis(20));
+    let nodes = create_ring(&net, 8, NodeConfig::default()).await;
+    for k in 0..20 {
+        nodes[0]
+            .put(format!("key{:}", k), blob("value"))
+            .await
+            .unwrap();
+    }
+
+    let metrics = &nodes[0].metrics;
+    assert!(metrics.stabilization_duration.count() > 0);
+    assert!(metrics.predecessor_changes.get() > 0);
+    assert!(metrics.successor_changes.get() > 0);
+    assert!(metrics.messages_sent.get("Notify") > 0);
+    // puts of keys the node owns itself need no lookup
+    assert!(metrics.lookup_hops.count() > 0 && metrics.lookup_hops.count() <= 20);
+    let received: u64 = nodes
+        .iter()
+        .map(|n| n.metrics.messages_received.get("Put"))
+        .sum();
+    assert_eq!(received, metrics.messages_sent.get("Put"));
+
+    let text = nodes[0].render_metrics().await;
+    assert!(text.contains("# TYPE accord_lookup_hops histogram"));
+    assert!(text.contains("accord_lookup_hops_bucket{le=\"+Inf\"}"));
+    assert!(text.contains("accord_messages_sent_total{message=\"Notify\"}"));
+
+    // every key is stored once
+    let mut stored = 0.0;
+    for node in nodes.iter() {
+        let text = node.render_metrics().await;
+        let line = text
+            .lines()
+            .find(|l| l.starts_with("accord_store_keys "))
+            .unwrap();
+        stored += line[18..].parse::<f64>().unwrap();
+    }
+    assert_eq!(stored, 20.0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn ring_walk_reports_every_node() {
+    let net = MemoryNetwork::new(9, Duration::from_millis(20));
+    let config = NodeConfig {
+        vnodes: 2,
+        ..NodeConfig::default()
+    };
+    let mut nodes = create_ring(&net, 8, config).await;
+    let ring = nodes[0].ring().await;
+    assert!(ring.is_consistent(), "{:?}", ring.inconsistencies);
+    assert_eq!(ring.nodes.len(), 16);
+    assert_eq!(ring.nodes[0].node.id, nodes[0].vnodes[0].id);
+    let share: f64 = ring.nodes.iter().map(|e| e.range_share().unwrap()).sum();
+    assert!((share - 1.0).abs() < 1e-9);
+
+    // the neighbors of a crashed node refer to it until they stabilize
+    net.disconnect(addr(5));
+    let ring = nodes[0].ring().await;
+    assert!(!ring.is_consistent());
+    assert!(ring
+        .inconsistencies
+        .iter()
+        .any(|i| matches!(i, Inconsistency::Unreachable(n) if n.addr == addr(5))));
+    nodes.remove(5);
+    stabilize(&nodes, 5).await;
+    let ring = nodes[0].ring().await;
+    assert!(ring.is_consistent(), "{:?}", ring.inconsistencies);
+    assert_eq!(ring.nodes.len(), 14);
+
+    // a node that never joined forms a cycle of its own
+    let other = spawn_node(&net, 100, config);
+    nodes[0].vnodes[0]
+        .successor_list
+        .lock()
+        .await
+        .push(Neighbor {
+            id: other.id,
+            addr: other.address,
+            web_addr: other.address,
+        });
+    let ring = nodes[0].ring().await;
+    assert_eq!(ring.nodes.len(), 16);
+    assert_eq!(ring.cycles.len(), 2);
+    assert!(ring.inconsistencies.contains(&Inconsistency::Cycles(2)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn waits_until_ring_is_stable() {
+    let net = MemoryNetwork::new(10, Duration::from_millis(20));
+    // a node alone is its own successor and predecessor
+    let alone = vec![spawn_node(&net, 9, NodeConfig::default())];
+    stabilize(&alone, 1).await;
+    let result = alone[0]
+        .wait_until_stable(Duration::from_secs(1), Duration::from_millis(100))
+        .await;
+    assert!(result.stable, "{:?}", result.ring.inconsistencies);
+    assert_eq!(result.checks, 1);
+
+    let mut nodes = create_ring(&net, 8, NodeConfig::default()).await;
+    assert!(nodes[0].is_stable().await);
+
+    net.disconnect(addr(3));
+    nodes.remove(3);
+    // nothing repairs the ring while the maintenance does not run
+    let result = nodes[0]
+        .wait_until_stable(Duration::from_secs(1), Duration::from_millis(100))
+        .await;
+    assert!(!result.stable);
+    assert!(result.elapsed >= Duration::from_secs(1));
+    assert!(result.checks > 1);
+
+    let maintained = nodes.clone();
+    let maintenance = tokio::spawn(async move {
+        loop {
+            stabilize(&maintained, 1).await;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+    });
+    let result = nodes[0]
+        .wait_until_stable(Duration::from_secs(60), Duration::from_millis(100))
+        .await;
+    maintenance.abort();
+    assert!(result.stable, "{:?}", result.ring.inconsistencies);
+    assert!(result.elapsed < Duration::from_secs(60));
+    assert_eq!(result.ring.nodes.len(), 7);
+    assert_ring(&nodes).await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn events_report_ring_changes() {
+    let net = MemoryNetwork::new(11, Duration::from_millis(20));
+    let config = NodeConfig {
+        replication_factor: 2,
+        ..NodeConfig::default()
+    };
+    let mut nodes = create_ring(&net, 6, config).await;
+    let mut subscriptions: Vec<_> = nodes.iter().map(|n| n.events.subscribe()).collect();
+    for k in 0..30 {
+        nodes[0]
+            .put(format!("key{:}", k), blob("value"))
+            .await
+            .unwrap();
+    }
+
+    let joining = spawn_node(&net, 6, config);
+    let mut joined = joining.events.subscribe();
+    joining.join(addr(0)).await.unwrap();
+    nodes.push(joining.clone());
+    stabilize(&nodes, 6).await;
+    let mut events = drain(&mut joined);
+    assert!(events.contains(&Event::Joined { entry: addr(0) }));
+    // nothing changed for the new node before it found its successor
+    assert!(!events.iter().any(|e| matches!(
+        e,
+        Event::PredecessorChanged {
+            old: None,
+            new: None,
+            ..
+        }
+    )));
+    events.extend(subscriptions.iter_mut().flat_map(drain));
+    // handed off by the successor of the new node
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, Event::KeysMigrated { to, keys, .. } if *to == addr(6) && *keys > 0)));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, Event::SuccessorChanged { new, .. } if new.addr == addr(6))));
+
+    joining.leave().await.unwrap();
+    net.disconnect(addr(6));
+    nodes.pop();
+    let events = drain(&mut joined);
+    assert_eq!(events.last(), Some(&Event::Left));
+    assert!(events.iter().any(
+        |e| matches!(e, Event::KeysMigrated { from, keys, .. } if *from == addr(6) && *keys > 0)
+    ));
+    stabilize(&nodes, 3).await;
+
+    net.disconnect(addr(3));
+    nodes.remove(3);
+    subscriptions.iter_mut().for_each(|s| {
+        drain(s);
+    });
+    stabilize(&nodes, 3).await;
+    let events: Vec<Event> = subscriptions.iter_mut().flat_map(drain).collect();
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, Event::Failover { failed, .. } if failed.addr == addr(3))));
+    assert!(events.iter().any(|e| matches!(
+        e,
+        Event::PredecessorChanged { old: Some(old), new: None, .. } if old.addr == addr(3)
+    )));
+
+    nodes[1].sim_crash().await.unwrap();
+    assert_eq!(drain(&mut subscriptions[1]), vec![Event::Crashed]);
+    let before = now_ms();
+    nodes[1].sim_recover().await.unwrap();
+    let after = now_ms();
+    // stamped when they were emitted, not when they were received
+    let recovered = subscriptions[1].try_recv().unwrap();
+    assert_eq!(recovered.event, Event::Recovered);
+    assert!(recovered.timestamp_ms >= before && recovered.timestamp_ms <= after);
+}
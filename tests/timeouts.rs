@@ -0,0 +1,100 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use accord::network::{self, Message, MessageError, NetworkConfig};
+use accord::transport::{TcpTransport, Transport};
+use serde_bytes::ByteBuf;
+use tokio::net::TcpListener;
+
+fn config() -> NetworkConfig {
+    NetworkConfig {
+        read_timeout: Duration::from_millis(100),
+        retries: 2,
+        retry_backoff: Duration::from_millis(50),
+        ..NetworkConfig::default()
+    }
+}
+
+// accepts connections but never says anything, returns the number of
+// accepted connections
+async fn silent_peer() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = accepted.clone();
+    tokio::spawn(async move {
+        let mut streams = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            // kept open so that the client has to time out
+            streams.push(stream);
+        }
+    });
+    (addr, accepted)
+}
+
+// completes the handshake but never answers a request, returns the
+// number of received requests
+async fn unanswering_peer() -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let counter = counter.clone();
+            tokio::spawn(network::serve_connection(
+                stream,
+                None,
+                None,
+                config(),
+                move |_, _| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    futures::future::pending::<Option<Message>>()
+                },
+            ));
+        }
+    });
+    (addr, requests)
+}
+
+#[tokio::test]
+async fn handshakes_time_out_and_are_retried() {
+    let (addr, accepted) = silent_peer().await;
+    let transport = TcpTransport::new(config(), None);
+    let start = Instant::now();
+    let result = transport.send(Message::Ping, addr, None).await;
+    assert!(
+        matches!(result, Err(MessageError::Timeout(a)) if a == addr),
+        "{:?}",
+        result
+    );
+    assert_eq!(accepted.load(Ordering::SeqCst), 3);
+    // three timeouts and the backoff of 50 and 100 ms in between
+    assert!(start.elapsed() >= Duration::from_millis(450));
+}
+
+#[tokio::test]
+async fn unanswered_requests_time_out() {
+    let (addr, requests) = unanswering_peer().await;
+    let transport = TcpTransport::new(config(), None);
+    let result = transport.send(Message::Ping, addr, None).await;
+    assert!(
+        matches!(result, Err(MessageError::Timeout(a)) if a == addr),
+        "{:?}",
+        result
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+    // a put could be applied twice, so it is only sent once
+    let put = Message::Put("key".to_string(), ByteBuf::from(vec![1]));
+    let result = transport.send(put, addr, None).await;
+    assert!(
+        matches!(result, Err(MessageError::Timeout(a)) if a == addr),
+        "{:?}",
+        result
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 4);
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use accord::api::{Blob, ChordNode};
+use accord::network::{self, Message, NetworkConfig};
+use accord::node::NodeConfig;
+use accord::tls::TlsConfig;
+use accord::transport::{TcpTransport, Transport};
+use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+use tokio::net::TcpListener;
+
+fn new_ca() -> Certificate {
+    let mut params = CertificateParams::new(Vec::new());
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    Certificate::from_params(params).unwrap()
+}
+
+// writes a certificate for 127.0.0.1 signed by the signer and loads it
+// together with the ca that is trusted
+fn tls_config(name: &str, signer: &Certificate, trusted: &Certificate) -> TlsConfig {
+    let dir = std::env::temp_dir().join(format!("accord-tls-{:}-{:}", std::process::id(), name));
+    fs::create_dir_all(&dir).unwrap();
+    let cert =
+        Certificate::from_params(CertificateParams::new(vec!["127.0.0.1".to_string()])).unwrap();
+    let path = |file: &str| -> PathBuf { dir.join(file) };
+    fs::write(
+        path("cert.pem"),
+        cert.serialize_pem_with_signer(signer).unwrap(),
+    )
+    .unwrap();
+    fs::write(path("key.pem"), cert.serialize_private_key_pem()).unwrap();
+    fs::write(path("ca.pem"), trusted.serialize_pem().unwrap()).unwrap();
+    TlsConfig::load(&path("cert.pem"), &path("key.pem"), &path("ca.pem")).unwrap()
+}
+
+async fn start_node(tls: TlsConfig) -> Arc<ChordNode> {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let node = Arc::new(ChordNode::with_storage(
+        addr,
+        addr,
+        NodeConfig::default(),
+        Box::new(HashMap::new()),
+        Box::new(HashMap::new()),
+        Some(tls.clone()),
+        None,
+    ));
+    let handler_node = node.clone();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let node = handler_node.clone();
+            let handler = move |msg: Message, receiver| {
+                let node = node.clone();
+                async move { node.handle_message(msg, receiver).await.unwrap_or(None) }
+            };
+            tokio::spawn(network::serve_connection(
+                stream,
+                Some(tls.clone()),
+                None,
+                NetworkConfig::default(),
+                handler,
+            ));
+        }
+    });
+    node
+}
+
+#[tokio::test]
+async fn nodes_with_certificates_form_ring() {
+    let ca = new_ca();
+    let a = start_node(tls_config("ring-a", &ca, &ca)).await;
+    let b = start_node(tls_config("ring-b", &ca, &ca)).await;
+    b.join(a.address).await.unwrap();
+    for _ in 0..3 {
+        for node in [&a, &b] {
+            let _ = node.stabilize().await;
+        }
+    }
+    assert_eq!(a.vnodes[0].successor.lock().await.addr, b.address);
+    assert_eq!(b.vnodes[0].successor.lock().await.addr, a.address);
+
+    let value = Blob {
+        content_type: None,
+        data: b"value".to_vec(),
+    };
+    for k in 0..10 {
+        a.put(format!("key{:}", k), value.clone()).await.unwrap();
+    }
+    for k in 0..10 {
+        let found = b.lookup(format!("key{:}", k)).await.unwrap();
+        assert_eq!(found, Some(value.clone()));
+    }
+}
+
+#[tokio::test]
+async fn peers_without_valid_certificate_are_rejected() {
+    let ca = new_ca();
+    let node = start_node(tls_config("reject-node", &ca, &ca)).await;
+    let config = NetworkConfig {
+        retries: 0,
+        ..NetworkConfig::default()
+    };
+
+    // plaintext
+    let transport = TcpTransport::new(config, None);
+    assert!(transport
+        .send(Message::Ping, node.address, None)
+        .await
+        .is_err());
+
+    // a certificate signed by another ca
+    let rogue = new_ca();
+    let transport = TcpTransport::with_security(
+        config,
+        None,
+        Some(tls_config("reject-rogue", &rogue, &ca)),
+        None,
+    );
+    assert!(transport
+        .send(Message::Ping, node.address, None)
+        .await
+        .is_err());
+
+    // a client that does not trust the ca of the node
+    let transport = TcpTransport::with_security(
+        config,
+        None,
+        Some(tls_config("reject-trust", &ca, &rogue)),
+        None,
+    );
+    assert!(transport
+        .send(Message::Ping, node.address, None)
+        .await
+        .is_err());
+
+    let transport = TcpTransport::with_security(
+        config,
+        None,
+        Some(tls_config("reject-valid", &ca, &ca)),
+        None,
+    );
+    let response = transport
+        .send(Message::Ping, node.address, None)
+        .await
+        .unwrap();
+    assert!(matches!(response, Some(Message::Pong)));
+}